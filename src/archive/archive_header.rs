@@ -8,7 +8,7 @@ pub struct ArchiveHeader
 
 impl ArchiveHeader
 {
-    pub fn new(directory_infos: Vec<DirectoryInfo>) -> Result<ArchiveHeader, ()>
+    pub fn new(directory_infos: &[DirectoryInfo]) -> Result<ArchiveHeader, ()>
     {
         let mut data = vec![];
         for info in directory_infos
@@ -0,0 +1,244 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+
+use sha2::{Digest, Sha256};
+
+use crate::compress::{Decompress, DecompressionError};
+use crate::io_utils::{FileInfo, create_tmp_file, remove_tmp_file};
+
+
+/// Pairs (chunk id, chunk size). The ids are numbered in the order the chunks first appear
+/// in the archive, and only the first appearance of a chunk stores its data.
+pub type ChunkList = Vec<(u64, u64)>;
+
+const MIN_CHUNK_SIZE: usize = 4 << 10;
+const AVERAGE_CHUNK_SIZE: usize = 16 << 10;
+pub const MAX_CHUNK_SIZE: usize = 64 << 10;
+
+// A stricter mask below the average size and a looser one above it
+// keep the chunk sizes close to the average. The high bits of the hash depend on more bytes.
+const MASK_SMALL: u64 = ((1 << 16) - 1) << 48;
+const MASK_LARGE: u64 = ((1 << 12) - 1) << 52;
+
+const GEAR: [u64; 256] = get_gear_table();
+
+/// Pseudorandom values for the rolling hash (SplitMix64), the same for every build.
+const fn get_gear_table() -> [u64; 256]
+{
+    let mut table = [0; 256];
+    let mut state: u64 = 0;
+
+    let mut index = 0;
+    while index < 256
+    {
+        state = state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut value = state;
+        value = (value ^ (value >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94D049BB133111EB);
+        table[index] = value ^ (value >> 31);
+
+        index += 1;
+    }
+
+    table
+}
+
+/// Returns the size of the first chunk of the data.
+fn find_cut_point(data: &[u8]) -> usize
+{
+    if data.len() <= MIN_CHUNK_SIZE
+    {
+        return data.len();
+    }
+
+    let end = data.len().min(MAX_CHUNK_SIZE);
+    let normal_end = end.min(AVERAGE_CHUNK_SIZE);
+
+    let mut hash: u64 = 0;
+    for (index, &byte) in data.iter().enumerate().take(end).skip(MIN_CHUNK_SIZE)
+    {
+        hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+
+        let mask = match index < normal_end
+        {
+            true => MASK_SMALL,
+            false => MASK_LARGE,
+        };
+
+        if hash & mask == 0
+        {
+            return index + 1;
+        }
+    }
+
+    end
+}
+
+/// Splits the file into chunks of variable size, cut where the content itself says so,
+/// so an insertion only changes the chunks around it. Returns the chunk sizes and hashes.
+pub fn split_into_chunks(path: &str) -> Result<Vec<(u64, Vec<u8>)>, String>
+{
+    let file = File::open(path)
+        .map_err(|_| format!("Could not open file {}", path))?;
+    let mut reader = BufReader::new(file);
+
+    let mut chunks = vec![];
+    let mut buffer: Vec<u8> = Vec::with_capacity(2 * MAX_CHUNK_SIZE);
+    let mut end_of_file = false;
+
+    loop
+    {
+        // Have at least the maximum chunk in the buffer, unless the file ends.
+        while !end_of_file && buffer.len() < MAX_CHUNK_SIZE
+        {
+            let mut block = [0; MAX_CHUNK_SIZE];
+            let bytes_read = reader.read(&mut block)
+                .map_err(|_| format!("Could not read file {}", path))?;
+
+            end_of_file = bytes_read == 0;
+            buffer.extend_from_slice(&block[..bytes_read]);
+        }
+
+        if buffer.is_empty()
+        {
+            return Ok(chunks);
+        }
+
+        let chunk_size = find_cut_point(&buffer);
+        let hash = Sha256::digest(&buffer[..chunk_size]).to_vec();
+
+        chunks.push((chunk_size as u64, hash));
+        buffer.drain(..chunk_size);
+    }
+}
+
+/// Keeps the chunks needed again later while a chunked archive is extracted.
+pub struct ChunkStore
+{
+    file: File,
+    path: String,
+    stored_chunks: HashMap<u64, u64>,
+    reused_chunks: HashSet<u64>,
+    next_chunk_id: u64,
+}
+
+impl ChunkStore
+{
+    /// Chunks referenced more than once in the archive must be kept after their first appearance.
+    pub fn new(chunk_lists: &[&ChunkList]) -> Result<ChunkStore, DecompressionError>
+    {
+        let mut reference_counts: HashMap<u64, usize> = HashMap::new();
+        for &(chunk_id, _) in chunk_lists.iter().copied().flatten()
+        {
+            *reference_counts.entry(chunk_id).or_default() += 1;
+        }
+
+        let reused_chunks = reference_counts.into_iter()
+            .filter(|&(_, count)| count > 1)
+            .map(|(chunk_id, _)| chunk_id)
+            .collect();
+
+        // The file is used as created, never opened again by its path, which someone could replace meanwhile.
+        let FileInfo
+        {
+            handle: file,
+            path
+        }
+            = create_tmp_file(".unarch")
+            .ok_or(DecompressionError::FileCreationError)?;
+
+        let chunk_store = ChunkStore
+        {
+            file,
+            path,
+            stored_chunks: HashMap::new(),
+            reused_chunks,
+            next_chunk_id: 0,
+        };
+
+        Ok(chunk_store)
+    }
+
+    fn read_stored_chunk(&mut self, chunk_id: u64, size: u64) -> Result<Vec<u8>, DecompressionError>
+    {
+        let offset = *self.stored_chunks.get(&chunk_id)
+            .ok_or(DecompressionError::BadFormat)?;
+
+        let mut chunk = vec![0; size as usize];
+        self.file.seek(SeekFrom::Start(offset))
+            .and_then(|_| self.file.read_exact(&mut chunk))
+            .map_err(|_| DecompressionError::Other)?;
+
+        Ok(chunk)
+    }
+
+    fn store_chunk(&mut self, chunk_id: u64, chunk: &[u8]) -> Result<(), DecompressionError>
+    {
+        let offset = self.file.seek(SeekFrom::End(0))
+            .map_err(|_| DecompressionError::Other)?;
+
+        self.file.write_all(chunk)
+            .map_err(|_| DecompressionError::Other)?;
+        self.stored_chunks.insert(chunk_id, offset);
+
+        Ok(())
+    }
+
+    /// Reads the new chunks of a file from the archive data and writes the whole file
+    /// to the output, if any. Every chunked file must pass here in the archive order.
+    pub fn read_file(&mut self, chunks: &ChunkList, archive_data: &mut dyn Decompress,
+                     output_filename: Option<&str>) -> Result<(), DecompressionError>
+    {
+        let mut output = match output_filename
+        {
+            Some(output_filename) => Some(File::create(output_filename)
+                .map_err(|_| DecompressionError::FileCreationError)?),
+            None => None,
+        };
+
+        for &(chunk_id, size) in chunks
+        {
+            let new_chunk = chunk_id == self.next_chunk_id;
+
+            // Old chunks are only needed when the file is written.
+            if !new_chunk && output.is_none()
+            {
+                continue;
+            }
+
+            let chunk = match new_chunk
+            {
+                true =>
+                {
+                    self.next_chunk_id += 1;
+                    let chunk = archive_data.decompress_bytes_to_memory(size as usize)?;
+
+                    if self.reused_chunks.contains(&chunk_id)
+                    {
+                        self.store_chunk(chunk_id, &chunk)?;
+                    }
+                    chunk
+                }
+
+                false => self.read_stored_chunk(chunk_id, size)?,
+            };
+
+            if let Some(output) = &mut output
+            {
+                output.write_all(&chunk)
+                    .map_err(|_| DecompressionError::FileCreationError)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for ChunkStore
+{
+    fn drop(&mut self)
+    {
+        let _ = remove_tmp_file(&self.path);
+    }
+}
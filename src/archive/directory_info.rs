@@ -1,43 +1,220 @@
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
 use std::fs;
-use walkdir::WalkDir;
+use std::fs::{File, Metadata};
+use std::io::{ErrorKind, copy};
+use std::time::UNIX_EPOCH;
+use ignore::WalkBuilder;
 use serde::{Deserialize, Serialize};
-use crate::io_utils::path_utils::get_superpath;
+use sha2::{Digest, Sha256};
+use crate::archive::chunking::{ChunkList, MAX_CHUNK_SIZE, split_into_chunks};
+use crate::archive::pack::ChangedFiles;
+use crate::io_utils::path_filter::PathFilter;
+use crate::io_utils::path_utils::{get_superpath, normalize_relative_path};
+
+/// Identifies a file on the disk regardless of its path (device, inode).
+pub type FileId = (u64, u64);
+
+#[cfg(unix)]
+fn get_file_id(metadata: &Metadata) -> Option<FileId>
+{
+    use std::os::unix::fs::MetadataExt;
+
+    // A file with only one link cannot be shared with any other path.
+    if metadata.nlink() < 2
+    {
+        return None;
+    }
+
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn get_file_id(_metadata: &Metadata) -> Option<FileId>
+{
+    None
+}
+
+/// Returns the modification time in seconds since the Unix epoch, as stored in the archive.
+pub fn get_modification_time(metadata: &Metadata) -> Option<u64>
+{
+    metadata.modified().ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+}
+
+/// A file whose data goes to the archive, as it was when the inputs were scanned.
+pub struct StoredFile
+{
+    pub source_path: String,
+    pub size: u64,
+    pub modified: Option<u64>,
+
+    /// The chunks of the file, if it was split into chunks.
+    pub chunks: Option<ChunkList>,
+
+    /// The data is read from the streamed input and stored in frames.
+    pub framed: bool,
+}
+
+fn is_false(value: &bool) -> bool
+{
+    !value
+}
 
 #[derive(Serialize, Deserialize)]
 struct FilesystemEntryInfo
 {
     path: String,
     size: Option<u64>,
+
+    // Modification time in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    modified: Option<u64>,
+
+    // Path (in the archive) of the file whose data this entry shares.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hard_link: Option<String>,
+
+    // Path (in the archive) of an earlier file with the same content.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    duplicate_of: Option<String>,
+
+    // The chunks the content is made of, if it was split into chunks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    chunks: Option<ChunkList>,
+
+    // The data of unknown size, e.g. from stdin, is stored in frames, each preceded by its size,
+    // up to an empty one. The size of such an entry is 0.
+    #[serde(default, skip_serializing_if = "is_false")]
+    framed: bool,
+
+    // Full path on the disk. Only needed while packing.
+    #[serde(skip)]
+    source_path: String,
+}
+
+/// Returns the path in the archive of a path on the disk
+/// under the input directory, which itself is stored as archive_root.
+fn get_archive_path(path: &str, directory_path: &str, archive_root: &str) -> String
+{
+    let relative_path = path.strip_prefix(directory_path)
+        .unwrap_or(path)
+        .trim_matches('/');
+
+    match (archive_root.is_empty(), relative_path.is_empty())
+    {
+        (true, _) => relative_path.to_string(),
+        (false, true) => archive_root.to_string(),
+        (false, false) => format!("{}/{}", archive_root, relative_path),
+    }
+}
+
+/// By default an input is stored under its own name.
+pub fn get_default_archive_root(directory_path: &str) -> String
+{
+    let directory_path = directory_path.replace('\\', "/");
+    let directory_superpath = get_superpath(&directory_path);
+
+    get_archive_path(&directory_path, &directory_superpath, "")
 }
 
 impl FilesystemEntryInfo
 {
-    fn new(path: &str, superpath: &str) -> FilesystemEntryInfo
+    /// A directory in the archive without a source on the disk, holding the entries below it.
+    fn new_archive_directory(path: String) -> FilesystemEntryInfo
     {
-        let size = if fs::metadata(path).unwrap().is_dir()
+        FilesystemEntryInfo
+        {
+            path,
+            size: None,
+            modified: None,
+            hard_link: None,
+            duplicate_of: None,
+            chunks: None,
+            framed: false,
+            source_path: String::new(),
+        }
+    }
+
+    fn is_archive_directory(&self) -> bool
+    {
+        self.source_path.is_empty() && self.size.is_none()
+    }
+
+    /// None if the path disappeared since it was listed and the change is only to be reported.
+    fn new(path: &str, archive_path: String, hard_link_targets: &mut HashMap<FileId, String>,
+           changed_files: &ChangedFiles) -> Result<Option<FilesystemEntryInfo>, String>
+    {
+        let metadata = match fs::metadata(path)
+        {
+            Ok(metadata) => metadata,
+
+            Err(err) if err.kind() == ErrorKind::NotFound =>
+            {
+                changed_files.add(path)?;
+                return Ok(None);
+            }
+
+            Err(_) => return Err(format!("Could not read the metadata of {}.", path)),
+        };
+        let size = if metadata.is_dir()
         {
             None
         }
         else
         {
-            let file_size = fs::metadata(path).unwrap().len();
+            let file_size = metadata.len();
             Some(file_size)
         };
 
-        let path = path.strip_prefix(superpath)
-            .unwrap_or(path)
-            .to_string();
+        let modified = get_modification_time(&metadata);
 
-        // Remove the initial slash if any.
-        let path = path.strip_prefix('/')
-            .unwrap_or(&path)
-            .to_string();
+        let source_path = path.to_string();
+        let path = archive_path;
 
-        FilesystemEntryInfo
+        // The first path of a hard linked file stores the data, the others only point to it.
+        let hard_link = match get_file_id(&metadata)
+        {
+            Some(file_id) if size.is_some() => match hard_link_targets.entry(file_id)
+            {
+                Entry::Occupied(target) => Some(target.get().clone()),
+                Entry::Vacant(target) =>
+                {
+                    target.insert(path.clone());
+                    None
+                }
+            },
+
+            _ => None,
+        };
+
+        Ok(Some(FilesystemEntryInfo
         {
             path,
             size,
-        }
+            modified,
+            hard_link,
+            duplicate_of: None,
+            chunks: None,
+            framed: false,
+            source_path,
+        }))
+    }
+}
+
+/// The path which disappeared while the directory was walked, if that is what the error is about.
+fn get_disappeared_path(err: &ignore::Error) -> Option<String>
+{
+    match err
+    {
+        ignore::Error::WithPath { path, err }
+            if err.io_error().is_some_and(|err| err.kind() == ErrorKind::NotFound) =>
+            Some(path.to_string_lossy().replace('\\', "/")),
+
+        ignore::Error::WithDepth { err, .. } => get_disappeared_path(err),
+
+        _ => None,
     }
 }
 
@@ -49,26 +226,176 @@ pub struct DirectoryInfo
 
 impl DirectoryInfo
 {
-    pub fn new(directory_path: &str) -> DirectoryInfo
+    /// The input directory is stored in the archive as archive_root.
+    /// Hard link targets are shared between all the directories packed into one archive,
+    /// so a file linked from different input paths is stored only once.
+    /// The filter decides which archive paths are packed. The data is saved later
+    /// by the entries of the header, so it always matches the header.
+    /// With respect_ignore_files the paths ignored by .gitignore, .ignore
+    /// and the global git excludes are skipped too, like ripgrep does. Hidden files are still packed.
+    /// The paths disappearing during the scan are handled by the policy of the changed files.
+    pub fn new(directory_path: &str, archive_root: &str, path_filter: &PathFilter, respect_ignore_files: bool,
+               hard_link_targets: &mut HashMap<FileId, String>, changed_files: &ChangedFiles)
+        -> Result<DirectoryInfo, String>
     {
-        let directory_superpath = get_superpath(directory_path);
+        let normalized_directory_path = directory_path.replace('\\', "/");
+
+        // Excluded directories are not even walked into.
+        let walk_filter = path_filter.clone();
+        let walk_directory_path = normalized_directory_path.clone();
+        let walk_archive_root = archive_root.to_string();
+
+        let mut selected_paths = vec![];
+        for entry in WalkBuilder::new(directory_path)
+            .standard_filters(respect_ignore_files)
+            .hidden(false)
+            .filter_entry(move |entry|
+            {
+                let path = entry.path().to_string_lossy().replace('\\', "/");
+                let archive_path = get_archive_path(&path, &walk_directory_path, &walk_archive_root);
+                !walk_filter.is_excluded(&archive_path, entry.path().is_dir())
+            })
+            .build()
+        {
+            let entry = match entry
+            {
+                Ok(entry) => entry,
+                Err(err) => match get_disappeared_path(&err)
+                {
+                    Some(path) =>
+                    {
+                        changed_files.add(&path)?;
+                        continue;
+                    }
+                    None => return Err(format!("Could not scan {}: {}", directory_path, err)),
+                },
+            };
+            let path = entry.path().to_str().unwrap().to_string()
+                .replace('\\', "/");
+            let archive_path = get_archive_path(&path, &normalized_directory_path, archive_root);
+            let is_directory = entry.path().is_dir();
+
+            if is_directory || path_filter.is_included(&archive_path, false)
+            {
+                selected_paths.push((path, archive_path, is_directory));
+            }
+        }
+
+        // With include patterns, only the directories on the way to the included files are kept,
+        // unless they are included themselves.
+        if path_filter.has_include_patterns()
+        {
+            let mut needed_directories = HashSet::new();
+            for (_, archive_path, _) in selected_paths.iter().filter(|(_, _, is_directory)| !is_directory)
+            {
+                needed_directories.extend(archive_path.match_indices('/')
+                    .map(|(index, _)| archive_path[..index].to_string()));
+            }
+
+            selected_paths.retain(|(_, archive_path, is_directory)| !is_directory
+                || needed_directories.contains(archive_path)
+                || path_filter.is_included(archive_path, true));
+        }
+
+        let mut entry_infos = vec![];
+        for (path, archive_path, _) in selected_paths
+        {
+            entry_infos.extend(FilesystemEntryInfo::new(&path, archive_path, hard_link_targets, changed_files)?);
+        }
 
+        Ok(DirectoryInfo
+        {
+            infos: entry_infos,
+        })
+    }
+
+    /// Takes exactly the listed paths in their order, without walking into the directories.
+    /// They are stored as given, only made relative, with the missing directories above them added.
+    pub fn from_path_list(paths: &[String], path_filter: &PathFilter,
+                          hard_link_targets: &mut HashMap<FileId, String>, changed_files: &ChangedFiles)
+        -> Result<DirectoryInfo, String>
+    {
         let mut entry_infos = vec![];
-        for entry in WalkDir::new(directory_path)
+        let mut directories = HashSet::new();
+
+        for path in paths
         {
-            let path = entry.unwrap().path().to_str().unwrap().to_string()
-                .replace("\\", "/");
+            let normalized_path = path.replace('\\', "/");
+            let archive_path = normalize_relative_path(normalized_path.trim_start_matches('/'))
+                .ok_or(format!("Invalid path {}.", path))?;
+
+            // E.g. "." listed by find.
+            if archive_path.is_empty()
+            {
+                continue;
+            }
+
+            let is_directory = fs::metadata(path)
+                .map_err(|_| format!("Path {} does not exist.", path))?
+                .is_dir();
+
+            // With include patterns, the directories on the way to the included files are added anyway.
+            if path_filter.is_excluded(&archive_path, is_directory)
+                || !path_filter.is_included(&archive_path, is_directory)
+            {
+                continue;
+            }
+
+            entry_infos.extend(archive_path.match_indices('/')
+                .map(|(index, _)| archive_path[..index].to_string())
+                .filter(|parent_path| directories.insert(parent_path.clone()))
+                .map(FilesystemEntryInfo::new_archive_directory));
+
+            // A directory may be listed after its content.
+            if is_directory && !directories.insert(archive_path.clone())
+            {
+                continue;
+            }
 
-            let entry_info = FilesystemEntryInfo::new(&path, &directory_superpath);
-            entry_infos.push(entry_info);
+            entry_infos.extend(FilesystemEntryInfo::new(path, archive_path, hard_link_targets, changed_files)?);
         }
 
-        DirectoryInfo
+        Ok(DirectoryInfo
         {
             infos: entry_infos,
+        })
+    }
+
+    /// The file read from a stream, e.g. stdin, which can be read only once, as it goes.
+    pub fn from_streamed_input(archive_path: String) -> DirectoryInfo
+    {
+        let entry_info = FilesystemEntryInfo
+        {
+            size: Some(0),
+            framed: true,
+            ..FilesystemEntryInfo::new_archive_directory(archive_path)
+        };
+
+        DirectoryInfo
+        {
+            infos: vec![entry_info],
         }
     }
 
+    /// An input stored deeper in the archive needs the directories above it.
+    /// Adds those which no earlier input has added yet.
+    pub fn add_parent_directories(&mut self, added_directories: &mut HashSet<String>)
+    {
+        let root = match self.infos.first()
+        {
+            Some(info) => info.path.clone(),
+            None => return,
+        };
+
+        let parent_infos: Vec<FilesystemEntryInfo> = root.match_indices('/')
+            .map(|(index, _)| root[..index].to_string())
+            .filter(|parent_path| added_directories.insert(parent_path.clone()))
+            .map(FilesystemEntryInfo::new_archive_directory)
+            .collect();
+
+        self.infos.splice(0..0, parent_infos);
+    }
+
     pub fn to_bytes(&self) -> Result<Vec<u8>, ()>
     {
         let content = serde_json::to_string(self)
@@ -104,4 +431,280 @@ impl DirectoryInfo
 
         result
     }
+
+    /// Returns pairs (link path, target path) of the entries that have no data of their own.
+    pub fn get_hard_links(&self) -> Vec<(String, String)>
+    {
+        self.infos.iter()
+            .filter_map(|info| info.hard_link.as_ref()
+                .map(|target| (info.path.clone(), target.clone())))
+            .collect()
+    }
+
+    /// Returns pairs (duplicate path, path of the file with the same content).
+    pub fn get_duplicates(&self) -> Vec<(String, String)>
+    {
+        self.infos.iter()
+            .filter_map(|info| info.duplicate_of.as_ref()
+                .map(|original| (info.path.clone(), original.clone())))
+            .collect()
+    }
+
+    /// Returns pairs (path, path holding its data) of the hard links and the duplicates.
+    pub fn get_data_holders(&self) -> Vec<(String, String)>
+    {
+        [self.get_hard_links(), self.get_duplicates()].concat()
+    }
+
+    pub fn get_modification_times(&self) -> Vec<(String, u64)>
+    {
+        self.infos.iter()
+            .filter_map(|info| info.modified
+                .map(|modified| (info.path.clone(), modified)))
+            .collect()
+    }
+
+    pub fn remove_entries(&mut self, paths: &HashSet<String>)
+    {
+        self.infos.retain(|info| !paths.contains(&info.path));
+    }
+
+    /// Makes the hard links and the duplicates of old_target point to new_target instead.
+    /// The entry of new_target itself becomes the one holding the data.
+    pub fn redirect_data_holder(&mut self, old_target: &str, new_target: &str)
+    {
+        for info in &mut self.infos
+        {
+            for data_holder in [&mut info.hard_link, &mut info.duplicate_of]
+            {
+                if data_holder.as_deref() != Some(old_target)
+                {
+                    continue;
+                }
+
+                *data_holder = match info.path == new_target
+                {
+                    true => None,
+                    false => Some(new_target.to_string()),
+                };
+            }
+        }
+    }
+
+    pub fn get_chunk_lists(&self) -> Vec<(String, ChunkList)>
+    {
+        self.infos.iter()
+            .filter_map(|info| info.chunks.as_ref()
+                .map(|chunks| (info.path.clone(), chunks.clone())))
+            .collect()
+    }
+
+    pub fn get_framed_paths(&self) -> Vec<String>
+    {
+        self.infos.iter()
+            .filter(|info| info.framed)
+            .map(|info| info.path.clone())
+            .collect()
+    }
+
+    /// Returns the files whose data goes to the archive, in the archive order.
+    pub fn get_stored_files(&self) -> Vec<StoredFile>
+    {
+        self.infos.iter()
+            .filter(|info| info.hard_link.is_none() && info.duplicate_of.is_none())
+            .filter_map(|info| info.size.map(|size| StoredFile
+            {
+                source_path: info.source_path.clone(),
+                size,
+                modified: info.modified,
+                chunks: info.chunks.clone(),
+                framed: info.framed,
+            }))
+            .collect()
+    }
+
+    /// Returns the duplicates as they were when scanned. Their data is not read again,
+    /// so they must be checked for changes on their own.
+    pub fn get_duplicate_files(&self) -> Vec<StoredFile>
+    {
+        self.infos.iter()
+            .filter(|info| info.duplicate_of.is_some())
+            .filter_map(|info| info.size.map(|size| StoredFile
+            {
+                source_path: info.source_path.clone(),
+                size,
+                modified: info.modified,
+                chunks: None,
+                framed: false,
+            }))
+            .collect()
+    }
+}
+
+fn get_content_hash(path: &str) -> Result<Vec<u8>, String>
+{
+    let mut file = File::open(path)
+        .map_err(|_| format!("Could not open file {}", path))?;
+
+    let mut hasher = Sha256::new();
+    copy(&mut file, &mut hasher)
+        .map_err(|_| format!("Could not read file {}", path))?;
+
+    Ok(hasher.finalize().to_vec())
+}
+
+/// Inputs with the same names, or stored under the same archive paths, would overwrite each other.
+/// Returns an error listing every archive path used more than once and where it comes from.
+/// The directories added above the inputs may be the directories of other inputs, e.g. "a" for "a" and "a/b",
+/// so they collide only with the files.
+pub fn check_path_collisions(directory_infos: &[DirectoryInfo]) -> Result<(), String>
+{
+    let mut sources: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut colliding_paths = vec![];
+
+    let (archive_directories, entries): (Vec<&FilesystemEntryInfo>, Vec<&FilesystemEntryInfo>) =
+        directory_infos.iter()
+            .flat_map(|directory_info| &directory_info.infos)
+            .partition(|info| info.is_archive_directory());
+
+    let mut file_paths = HashSet::new();
+    for info in entries
+    {
+        let path_sources = sources.entry(&info.path).or_default();
+        if path_sources.len() == 1
+        {
+            colliding_paths.push(info.path.as_str());
+        }
+        path_sources.push(&info.source_path);
+
+        if info.size.is_some()
+        {
+            file_paths.insert(info.path.as_str());
+        }
+    }
+
+    for info in archive_directories
+    {
+        if file_paths.remove(info.path.as_str())
+        {
+            let path_sources = sources.get_mut(info.path.as_str()).unwrap();
+            if path_sources.len() == 1
+            {
+                colliding_paths.push(info.path.as_str());
+            }
+            path_sources.push(&info.source_path);
+        }
+    }
+
+    if colliding_paths.is_empty()
+    {
+        return Ok(());
+    }
+
+    let descriptions: Vec<String> = colliding_paths.iter()
+        .map(|path|
+        {
+            // The directories added above the inputs have no source.
+            let path_sources: Vec<&str> = sources[path].iter()
+                .map(|&source| match source.is_empty()
+                {
+                    true => "(archive directory)",
+                    false => source,
+                })
+                .collect();
+
+            format!("{} ({})", path, path_sources.join(", "))
+        })
+        .collect();
+
+    Err(format!("Paths stored more than once in the archive: {}.", descriptions.join("; ")))
+}
+
+/// Marks the files with the same content as an earlier file as its duplicates,
+/// so the content is stored once. Only the files of equal sizes are hashed.
+/// Returns the number of bytes saved.
+pub fn deduplicate(directory_infos: &mut [DirectoryInfo]) -> Result<u64, String>
+{
+    let is_candidate = |info: &FilesystemEntryInfo|
+        info.size.is_some_and(|size| size > 0) && info.hard_link.is_none() && info.duplicate_of.is_none();
+
+    let mut size_counts: HashMap<u64, usize> = HashMap::new();
+    for info in directory_infos.iter().flat_map(|directory| &directory.infos)
+    {
+        if is_candidate(info)
+        {
+            *size_counts.entry(info.size.unwrap()).or_default() += 1;
+        }
+    }
+
+    let mut originals: HashMap<(u64, Vec<u8>), String> = HashMap::new();
+    let mut saved_bytes = 0;
+
+    for info in directory_infos.iter_mut().flat_map(|directory| &mut directory.infos)
+    {
+        if !is_candidate(info)
+        {
+            continue;
+        }
+
+        let size = info.size.unwrap();
+        if size_counts[&size] < 2
+        {
+            continue;
+        }
+
+        let hash = get_content_hash(&info.source_path)?;
+        match originals.entry((size, hash))
+        {
+            Entry::Occupied(original) =>
+            {
+                info.duplicate_of = Some(original.get().clone());
+                saved_bytes += size;
+            }
+            Entry::Vacant(original) =>
+            {
+                original.insert(info.path.clone());
+            }
+        }
+    }
+
+    Ok(saved_bytes)
+}
+
+/// Splits the big files into chunks, so the chunks repeated in the archive are stored once.
+/// Returns the number of bytes saved.
+pub fn split_files_into_chunks(directory_infos: &mut [DirectoryInfo]) -> Result<u64, String>
+{
+    let mut chunk_ids: HashMap<Vec<u8>, u64> = HashMap::new();
+    let mut saved_bytes = 0;
+
+    for info in directory_infos.iter_mut().flat_map(|directory| &mut directory.infos)
+    {
+        let is_big_file = info.size.is_some_and(|size| size > MAX_CHUNK_SIZE as u64);
+        if !is_big_file || info.hard_link.is_some() || info.duplicate_of.is_some()
+        {
+            continue;
+        }
+
+        let mut chunks = vec![];
+        for (size, hash) in split_into_chunks(&info.source_path)?
+        {
+            let next_chunk_id = chunk_ids.len() as u64;
+            let chunk_id = match chunk_ids.entry(hash)
+            {
+                Entry::Occupied(chunk_id) =>
+                {
+                    saved_bytes += size;
+                    *chunk_id.get()
+                }
+                Entry::Vacant(chunk_id) => *chunk_id.insert(next_chunk_id),
+            };
+
+            chunks.push((chunk_id, size));
+        }
+
+        info.chunks = Some(chunks);
+    }
+
+    Ok(saved_bytes)
 }
@@ -1,56 +1,251 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Display, Formatter};
+use std::fs;
 use std::fs::{File, create_dir, create_dir_all};
-use std::io::Read;
+use std::io::{Read, Write};
 use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
 
 use crate::io_utils::byte_buffer::ByteBuffer;
-use crate::io_utils::path_utils::{get_superpath, is_a_subdirectory};
-use crate::io_utils::{HUFFMAN_SIGNATURE, LZ77_SIGNATURE, bytes_to_u64};
+use crate::io_utils::path_filter::PathSelection;
+use crate::io_utils::path_utils::{is_path_within, normalize_relative_path};
+use crate::io_utils::{ENCRYPTED_SIGNATURE, HUFFMAN_SIGNATURE, LZ77_SIGNATURE, VOLUME_SIGNATURE, FileInfo,
+                      bytes_to_u64, create_tmp_file, get_signature_bytes, remove_tmp_file};
 
+use crate::archive::chunking::{ChunkList, ChunkStore};
 use crate::archive::directory_info::DirectoryInfo;
+use crate::archive::volumes::VolumeReader;
 
-use crate::compress::Decompress;
+use crate::archive::pack::{ChangedFiles, Repacking};
+
+use crate::compress::{CompressionMethod, Decompress};
 use crate::compress::DecompressionError;
 
 use crate::compress::huffman::HuffmanDecompressor;
 use crate::compress::lz77::LZ77Decompressor;
 
+use crate::encrypt::{DecryptionKey, EncryptedArchive};
+
+
+/// What to do when a file being extracted already exists in the output directory.
+#[derive(Clone, Copy, PartialEq)]
+pub enum OverwritePolicy
+{
+    Skip,
+    Overwrite,
+    OverwriteIfNewer,
+    Rename,
+    Fail,
+}
+
+/// Changes the extracted paths, after the part left out by the selection.
+/// The leading components are stripped first, then the first matching prefix is rewritten,
+/// then the hierarchy is flattened. Paths with nothing left are not extracted.
+#[derive(Clone, Default)]
+pub struct PathRemapping
+{
+    pub strip_components: usize,
+
+    /// Pairs (old prefix, new prefix), compared by whole path components.
+    pub prefix_rewrites: Vec<(String, String)>,
+
+    /// Put all the files directly into the output directory. Directories are not created then.
+    pub flatten: bool,
+}
+
+impl PathRemapping
+{
+    fn apply(&self, path: &str, is_directory: bool) -> Option<String>
+    {
+        let components: Vec<&str> = path.split('/')
+            .filter(|component| !component.is_empty())
+            .collect();
+        let path = components.get(self.strip_components..)?.join("/");
+
+        let path = match self.prefix_rewrites.iter()
+            .find(|(old_prefix, _)| is_path_within(old_prefix, &path))
+        {
+            Some((old_prefix, new_prefix)) =>
+                format!("{}/{}", new_prefix, &path[old_prefix.len()..])
+                    .split('/')
+                    .filter(|component| !component.is_empty())
+                    .collect::<Vec<&str>>()
+                    .join("/"),
+            None => path,
+        };
+
+        let path = match (self.flatten, is_directory)
+        {
+            (true, true) => return None,
+            (true, false) => path.rsplit('/').next().unwrap_or_default().to_string(),
+            (false, _) => path,
+        };
+
+        (!path.is_empty()).then_some(path)
+    }
+}
+
+#[derive(Default)]
+pub struct ExtractionReport
+{
+    pub skipped_paths: Vec<String>,
+    pub renamed_paths: Vec<(String, String)>,
+
+    /// Hard links and duplicates not extracted, because their file was not, e.g. it was skipped.
+    pub unlinked_paths: Vec<String>,
+}
 
 pub struct Extractor
 {
-    decompressor: Box<dyn Decompress>,
+    // None if the archive is encrypted and was opened without a password.
+    decompressor: Option<Box<dyn Decompress>>,
+    compression_method: Option<CompressionMethod>,
+    encrypted: bool,
+    split: bool,
+    directory_infos: Vec<DirectoryInfo>,
     archive_info: Vec<(String, Option<u64>)>,
+    // Paths without data of their own (hard links and duplicates) and the paths holding it.
+    hard_links: HashMap<String, String>,
+    duplicates: HashSet<String>,
+    chunk_lists: HashMap<String, ChunkList>,
+    // Paths whose data is stored in frames.
+    framed_paths: HashSet<String>,
+    modification_times: HashMap<String, u64>,
+    trusted: bool,
+    overwrite_policy: OverwritePolicy,
+    path_remapping: PathRemapping,
 }
 
 impl Extractor
 {
+    /// An encrypted archive can be opened without a password only if its header is not encrypted.
+    /// Then its content can be listed, but not extracted.
+    /// A split archive is opened by the path of its first volume.
     pub fn new(archive_filename: String) -> Result<Extractor, DecompressionError>
     {
-        let mut archive_file = File::open(archive_filename)
+        Self::open(archive_filename, None)
+    }
+
+    pub fn with_password(archive_filename: String, password: &str)
+        -> Result<Extractor, DecompressionError>
+    {
+        Self::open(archive_filename, Some(DecryptionKey::Password(password.to_string())))
+    }
+
+    /// Opens an archive encrypted to public keys, given the content of an identity file
+    /// holding one of the matching secret keys.
+    pub fn with_identity(archive_filename: String, identity: &str)
+        -> Result<Extractor, DecompressionError>
+    {
+        Self::open(archive_filename, Some(DecryptionKey::Identity(identity.to_string())))
+    }
+
+    fn read_signature(archive_file: &mut impl Read) -> Result<Vec<u8>, DecompressionError>
+    {
+        let mut signature: Vec<u8> = vec![0; get_signature_bytes(HUFFMAN_SIGNATURE).len()];
+        archive_file.read_exact(&mut signature)
+            .map_err(|_| DecompressionError::BadFormat)?;
+
+        Ok(signature)
+    }
+
+    /// Opens an archive read from a stream, e.g. stdin, which is read only once, from the beginning.
+    /// Encrypted and split archives need the archive file.
+    pub fn from_reader(mut archive_reader: impl Read + 'static) -> Result<Extractor, DecompressionError>
+    {
+        let signature = Self::read_signature(&mut archive_reader)?;
+
+        if signature == get_signature_bytes(ENCRYPTED_SIGNATURE) || signature == get_signature_bytes(VOLUME_SIGNATURE)
+        {
+            return Err(DecompressionError::NotStreamable);
+        }
+
+        Self::open_compressed(signature, archive_reader)
+    }
+
+    pub fn open(archive_filename: String, decryption_key: Option<DecryptionKey>)
+        -> Result<Extractor, DecompressionError>
+    {
+        let mut archive_file = File::open(&archive_filename)
             .map_err(|_| DecompressionError::FileOpenError)?;
 
-        // Check signature.
-        let huffman_signature: Vec<u8> = HUFFMAN_SIGNATURE.to_be_bytes()
-            .into_iter().skip_while(|&byte| byte == 0)
-            .collect();
+        let signature = Self::read_signature(&mut archive_file)?;
+        match signature == get_signature_bytes(VOLUME_SIGNATURE)
+        {
+            true => Self::open_volumes(&archive_filename, decryption_key),
+            false =>
+            {
+                let archive_size = archive_file.metadata()
+                    .map_err(|_| DecompressionError::FileOpenError)?
+                    .len();
 
-        let lz77_signature: Vec<u8> = LZ77_SIGNATURE.to_be_bytes()
-            .into_iter().skip_while(|&byte| byte == 0)
-            .collect();
+                Self::open_file(signature, archive_file, archive_size, decryption_key)
+            }
+        }
+    }
 
+    /// Reads the volumes one after another, as they are, so they are not joined anywhere.
+    fn open_volumes(first_volume_path: &str, decryption_key: Option<DecryptionKey>)
+        -> Result<Extractor, DecompressionError>
+    {
+        let mut volume_reader = VolumeReader::new(first_volume_path)?;
+        let archive_size = volume_reader.get_data_size();
 
-        let mut signature: Vec<u8> = vec![0; huffman_signature.len()];
-        archive_file.read(&mut signature)
-            .map_err(|_| DecompressionError::BadFormat)?;
+        let signature = Self::read_signature(&mut volume_reader)?;
+        let mut extractor = Self::open_file(signature, volume_reader, archive_size, decryption_key)?;
 
+        extractor.split = true;
+        Ok(extractor)
+    }
 
-        let mut decompressor: Box<dyn Decompress> = if signature == huffman_signature
+    fn open_file(signature: Vec<u8>, archive_file: impl Read + 'static, archive_size: u64,
+                 decryption_key: Option<DecryptionKey>)
+        -> Result<Extractor, DecompressionError>
+    {
+        if signature != get_signature_bytes(ENCRYPTED_SIGNATURE)
+        {
+            return Self::open_compressed(signature, archive_file);
+        }
+
+        let encrypted_archive = EncryptedArchive::new(Box::new(archive_file), archive_size)?;
+        let mut extractor = match decryption_key
+        {
+            Some(decryption_key) => Self::open_decrypted(encrypted_archive, &decryption_key)?,
+
+            None =>
+            {
+                let header = encrypted_archive.get_visible_header()
+                    .ok_or(DecompressionError::PasswordRequired)?;
+
+                let directory_infos = Self::parse_header(header[8..].to_vec());
+                Self::from_directory_infos(directory_infos, None)
+            }
+        };
+
+        extractor.encrypted = true;
+        Ok(extractor)
+    }
+
+    /// Decrypts the archive while it is decompressed, so the decrypted data is not saved anywhere.
+    fn open_decrypted(encrypted_archive: EncryptedArchive, decryption_key: &DecryptionKey)
+        -> Result<Extractor, DecompressionError>
+    {
+        let mut decrypted_archive = encrypted_archive.decrypt(decryption_key)?;
+
+        let signature = Self::read_signature(&mut decrypted_archive)?;
+        Self::open_compressed(signature, decrypted_archive)
+    }
+
+    fn open_compressed(signature: Vec<u8>, archive_file: impl Read + 'static) -> Result<Extractor, DecompressionError>
+    {
+        let (mut decompressor, compression_method): (Box<dyn Decompress>, CompressionMethod) =
+            if signature == get_signature_bytes(HUFFMAN_SIGNATURE)
         {
-            Box::new(HuffmanDecompressor::new(archive_file)?)
+            (Box::new(HuffmanDecompressor::new(archive_file)?), CompressionMethod::HUFFMAN)
         }
-        else if signature == lz77_signature
+        else if signature == get_signature_bytes(LZ77_SIGNATURE)
         {
-            Box::new(LZ77Decompressor::new(archive_file)?)
+            (Box::new(LZ77Decompressor::new(archive_file)?), CompressionMethod::LZ77)
         }
         else
         {
@@ -62,6 +257,15 @@ impl Extractor
             bytes_to_u64(decompressor.decompress_bytes_to_memory(8)?);
 
         let header_data = decompressor.decompress_bytes_to_memory(header_size as usize)?;
+        let directory_infos = Self::parse_header(header_data);
+
+        let mut extractor = Self::from_directory_infos(directory_infos, Some(decompressor));
+        extractor.compression_method = Some(compression_method);
+        Ok(extractor)
+    }
+
+    fn parse_header(header_data: Vec<u8>) -> Vec<DirectoryInfo>
+    {
         let mut header_data = ByteBuffer::new(header_data);
 
         let mut directory_infos = vec![];
@@ -73,17 +277,60 @@ impl Extractor
             directory_infos.push(directory_info);
         }
 
+        directory_infos
+    }
+
+    fn from_directory_infos(directory_infos: Vec<DirectoryInfo>,
+                            decompressor: Option<Box<dyn Decompress>>) -> Extractor
+    {
         let archive_info: Vec<(String, Option<u64>)> = directory_infos.iter()
             .flat_map(|info| info.get_paths_and_sizes())
             .collect();
 
-        let extractor = Extractor
+        let hard_links: HashMap<String, String> = directory_infos.iter()
+            .flat_map(|info| info.get_data_holders())
+            .collect();
+
+        let duplicates: HashSet<String> = directory_infos.iter()
+            .flat_map(|info| info.get_duplicates())
+            .map(|(duplicate, _)| duplicate)
+            .collect();
+
+        let chunk_lists: HashMap<String, ChunkList> = directory_infos.iter()
+            .flat_map(|info| info.get_chunk_lists())
+            .collect();
+
+        let framed_paths: HashSet<String> = directory_infos.iter()
+            .flat_map(|info| info.get_framed_paths())
+            .collect();
+
+        let modification_times: HashMap<String, u64> = directory_infos.iter()
+            .flat_map(|info| info.get_modification_times())
+            .collect();
+
+        Extractor
         {
             decompressor,
+            compression_method: None,
+            encrypted: false,
+            split: false,
+            directory_infos,
             archive_info,
-        };
+            hard_links,
+            duplicates,
+            chunk_lists,
+            framed_paths,
+            modification_times,
+            trusted: false,
+            overwrite_policy: OverwritePolicy::Skip,
+            path_remapping: PathRemapping::default(),
+        }
+    }
 
-        Ok(extractor)
+    /// True if the archive is encrypted and was opened without a password.
+    pub fn requires_password(&self) -> bool
+    {
+        self.decompressor.is_none()
     }
 
     pub fn get_archive_info(&self) -> &Vec<(String, Option<u64>)>
@@ -91,54 +338,518 @@ impl Extractor
         &self.archive_info
     }
 
-    pub fn extract_paths(&mut self, paths_to_extract: Vec<String>, output_directory: String)
+    /// None if the archive is encrypted and was opened without a password.
+    pub fn get_compression_method(&self) -> Option<CompressionMethod>
+    {
+        self.compression_method
+    }
+
+    pub fn is_encrypted(&self) -> bool
+    {
+        self.encrypted
+    }
+
+    /// True if the archive was read from volumes.
+    pub fn is_split(&self) -> bool
+    {
+        self.split
+    }
+
+    /// True if some files were split into chunks shared with other files.
+    pub fn is_chunked(&self) -> bool
+    {
+        !self.chunk_lists.is_empty()
+    }
+
+    /// A trusted archive is extracted without checking if its paths stay
+    /// inside the output directory.
+    pub fn set_trusted(&mut self, trusted: bool)
+    {
+        self.trusted = trusted;
+    }
+
+    pub fn set_overwrite_policy(&mut self, overwrite_policy: OverwritePolicy)
+    {
+        self.overwrite_policy = overwrite_policy;
+    }
+
+    pub fn set_path_remapping(&mut self, path_remapping: PathRemapping)
+    {
+        self.path_remapping = path_remapping;
+    }
+
+    fn get_output_path(&self, path: &str, is_directory: bool, selection: &PathSelection, output_directory: &str)
+        -> Result<Option<String>, DecompressionError>
+    {
+        let superpath_to_be_stripped = match selection.get_base(path)
+        {
+            Some(base) => base,
+            None => return Ok(None),
+        };
+
+        let path_stripped = path.strip_prefix(&superpath_to_be_stripped)
+            .expect("Bad path stripping.");
+
+        let path_stripped = match self.path_remapping.apply(path_stripped, is_directory)
+        {
+            Some(path_remapped) => path_remapped,
+            None => return Ok(None),
+        };
+
+        if self.trusted
+        {
+            return Ok(Some(format!("{}/{}", output_directory, path_stripped)));
+        }
+
+        // Both the path from the archive and the part of it being extracted
+        // must not be absolute nor go up with "..".
+        let path_stripped = normalize_relative_path(path)
+            .and_then(|_| normalize_relative_path(path_stripped.trim_start_matches('/')))
+            .filter(|path_stripped| !path_stripped.is_empty())
+            .ok_or(DecompressionError::UnsafePath(path.to_string()))?;
+
+        Ok(Some(format!("{}/{}", output_directory, path_stripped)))
+    }
+
+    /// Checks if an output path does not lead outside the output directory
+    /// through a symbolic link already present on the disk.
+    fn check_output_path(&self, output_path: &str, canonical_output_directory: &Path)
         -> Result<(), DecompressionError>
     {
+        if self.trusted
+        {
+            return Ok(());
+        }
+
+        // Writing to a (maybe dangling) symbolic link would write to its target.
+        if let Ok(metadata) = fs::symlink_metadata(output_path)
+        {
+            if metadata.file_type().is_symlink()
+            {
+                return Err(DecompressionError::UnsafePath(output_path.to_string()));
+            }
+        }
+
+        self.check_output_parent(output_path, canonical_output_directory)
+    }
+
+    /// Checks if the directory of an output path is inside the output directory,
+    /// so the path may be looked at or removed.
+    fn check_output_parent(&self, output_path: &str, canonical_output_directory: &Path)
+        -> Result<(), DecompressionError>
+    {
+        if self.trusted
+        {
+            return Ok(());
+        }
+
+        let output_path = Path::new(output_path);
+        let unsafe_path_error =
+            || DecompressionError::UnsafePath(output_path.to_string_lossy().to_string());
+
+        let canonical_parent = output_path.parent()
+            .ok_or_else(unsafe_path_error)?
+            .canonicalize()
+            .map_err(|_| DecompressionError::FileCreationError)?;
+
+        if !canonical_parent.starts_with(canonical_output_directory)
+        {
+            return Err(unsafe_path_error());
+        }
+
+        Ok(())
+    }
+
+    fn create_hard_link(target_path: &str, link_path: &str) -> Result<(), DecompressionError>
+    {
+        // Some filesystems do not support hard links. Copy the file then.
+        fs::hard_link(target_path, link_path)
+            .or_else(|_| fs::copy(target_path, link_path).map(|_| ()))
+            .map_err(|_| DecompressionError::FileCreationError)
+    }
+
+    /// Returns the first path of the form "name (n).extension" not present on the disk.
+    fn get_free_path(output_path: &str) -> String
+    {
+        let path = Path::new(output_path);
+        let stem = path.file_stem()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let extension = path.extension()
+            .map(|extension| format!(".{}", extension.to_string_lossy()))
+            .unwrap_or_default();
+
+        (1..)
+            .map(|number| path.with_file_name(format!("{} ({}){}", stem, number, extension)))
+            .find(|candidate| fs::symlink_metadata(candidate).is_err())
+            .map(|candidate| candidate.to_string_lossy().to_string())
+            .unwrap()
+    }
+
+    fn is_newer_than_file_on_disk(&self, path: &str, output_path: &str) -> bool
+    {
+        let archive_time = match self.modification_times.get(path)
+        {
+            Some(&seconds) => UNIX_EPOCH + Duration::from_secs(seconds),
+            None => return false,
+        };
+
+        match fs::metadata(output_path).and_then(|metadata| metadata.modified())
+        {
+            Ok(disk_time) => archive_time > disk_time,
+            Err(_) => false,
+        }
+    }
+
+    /// Applies the overwrite policy to an output path.
+    /// Returns the path to write to or None if the file is to be skipped.
+    fn resolve_existing_path(&self, path: &str, output_path: String, canonical_output_directory: &Path,
+                             report: &mut ExtractionReport) -> Result<Option<String>, DecompressionError>
+    {
+        // Nothing outside the output directory may be removed.
+        self.check_output_parent(&output_path, canonical_output_directory)?;
+
+        if fs::symlink_metadata(&output_path).is_err()
+        {
+            return Ok(Some(output_path));
+        }
+
+        let overwrite = match self.overwrite_policy
+        {
+            OverwritePolicy::Skip => false,
+            OverwritePolicy::Overwrite => true,
+            OverwritePolicy::OverwriteIfNewer => self.is_newer_than_file_on_disk(path, &output_path),
+
+            OverwritePolicy::Rename =>
+            {
+                let free_path = Self::get_free_path(&output_path);
+                report.renamed_paths.push((output_path, free_path.clone()));
+                return Ok(Some(free_path));
+            }
+
+            OverwritePolicy::Fail => return Err(DecompressionError::PathExists(output_path)),
+        };
+
+        if !overwrite
+        {
+            report.skipped_paths.push(output_path);
+            return Ok(None);
+        }
+
+        // Remove the old file instead of truncating it,
+        // so other hard links to it stay untouched.
+        fs::remove_file(&output_path)
+            .map_err(|_| DecompressionError::FileCreationError)?;
+
+        Ok(Some(output_path))
+    }
+
+    fn set_modification_time(&self, path: &str, output_path: &str)
+    {
+        if let Some(&seconds) = self.modification_times.get(path)
+        {
+            // The time is not essential. Ignore the errors.
+            let _ = File::options()
+                .write(true)
+                .open(output_path)
+                .and_then(|file| file.set_modified(UNIX_EPOCH + Duration::from_secs(seconds)));
+        }
+    }
+
+    /// The paths to extract are archive paths or glob patterns, see PathSelection.
+    pub fn extract_paths(&mut self, paths_to_extract: Vec<String>, output_directory: String)
+        -> Result<ExtractionReport, DecompressionError>
+    {
+        if self.requires_password()
+        {
+            return Err(DecompressionError::PasswordRequired);
+        }
+
+        let mut selection = PathSelection::new(&paths_to_extract)
+            .map_err(DecompressionError::InvalidPattern)?;
+        selection.add_directories_on_the_way(self.archive_info.iter().map(|(path, _)| path.as_str()));
+        self.check_output_conflicts(&selection, &output_directory)?;
+
         create_dir_all(&output_directory)
             .map_err(|_| DecompressionError::Other)?;
+        let canonical_output_directory = Path::new(&output_directory).canonicalize()
+            .map_err(|_| DecompressionError::Other)?;
+
+        // A selected hard link or duplicate may point to a file that is not selected.
+        // The data of such a file is kept in a temporary file until the first path linking to it.
+        let mut unselected_link_targets: HashSet<String> = HashSet::new();
+        for (path, _) in &self.archive_info
+        {
+            if let Some(target) = self.hard_links.get(path)
+            {
+                if self.get_output_path(target, false, &selection, &output_directory)?.is_none()
+                    && self.get_output_path(path, false, &selection, &output_directory)?.is_some()
+                {
+                    unselected_link_targets.insert(target.clone());
+                }
+            }
+        }
+
+        let mut chunk_store = match self.is_chunked()
+        {
+            true => Some(ChunkStore::new(&self.chunk_lists.values().collect::<Vec<_>>())?),
+            false => None,
+        };
+
+        let mut pending_targets: HashMap<String, String> = HashMap::new();
+        let result = self.extract_entries(&selection, &output_directory, &canonical_output_directory,
+                                          &unselected_link_targets, &mut pending_targets, &mut chunk_store);
+
+        for tmp_file_path in pending_targets.values()
+        {
+            let _ = remove_tmp_file(tmp_file_path);
+        }
+
+        result
+    }
+
+    /// Extracts everything in the archive to the output directory.
+    pub fn extract_all(&mut self, output_directory: String) -> Result<ExtractionReport, DecompressionError>
+    {
+        let top_paths = self.archive_info.iter()
+            .map(|(path, _)| path)
+            .filter(|path| !path.contains('/'))
+            .cloned()
+            .collect();
+
+        self.extract_paths(top_paths, output_directory)
+    }
+
+    /// Stripping, rewriting and flattening the paths may send different entries to the same output path.
+    /// Directories can be merged, anything else is refused before extracting anything.
+    fn check_output_conflicts(&self, selection: &PathSelection, output_directory: &str)
+        -> Result<(), DecompressionError>
+    {
+        let mut output_paths: HashMap<String, bool> = HashMap::new();
 
         for (path, size) in &self.archive_info
         {
-            // Check if this path is a subdirectory of some given path to be extracted.
-            match paths_to_extract.iter()
-                .find(|&path_to_extract| is_a_subdirectory(path_to_extract, path))
+            let is_directory = size.is_none();
+            let output_path = match self.get_output_path(path, is_directory, selection, output_directory)?
             {
-                None => // This path is not to be extracted. Ignore and continue.
+                Some(output_path) => output_path,
+                None => continue,
+            };
+
+            match output_paths.insert(output_path.clone(), is_directory)
+            {
+                Some(true) if is_directory => {},
+                Some(_) => return Err(DecompressionError::PathConflict(output_path)),
+                None => {},
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves the data of an unselected file from its temporary file to the path linking to it.
+    fn move_pending_target(tmp_file_path: &str, output_path: &str) -> Result<(), DecompressionError>
+    {
+        fs::rename(tmp_file_path, output_path)
+            .or_else(|_| fs::copy(tmp_file_path, output_path)
+                .and_then(|_| fs::remove_file(tmp_file_path)))
+            .map_err(|_| DecompressionError::FileCreationError)
+    }
+
+    /// Reads the data of a stored file from the archive to the output file, if any.
+    /// The data in frames has no size of its own, so the bytes are None then.
+    fn read_file_data(decompressor: &mut dyn Decompress, chunks: Option<&ChunkList>, bytes: Option<usize>,
+                      output_filename: Option<&str>, chunk_store: &mut Option<ChunkStore>)
+        -> Result<(), DecompressionError>
+    {
+        let bytes = match bytes
+        {
+            Some(bytes) => bytes,
+            None => return Self::read_frames(decompressor, output_filename),
+        };
+
+        match (chunks, chunk_store, output_filename)
+        {
+            (Some(chunks), Some(chunk_store), output_filename) =>
+                chunk_store.read_file(chunks, decompressor, output_filename),
+
+            (_, _, Some(output_filename)) => decompressor.decompress_bytes_to_file(output_filename, bytes),
+
+            // Not extracted, but the data must still be read to keep the stream in sync.
+            (_, _, None) => decompressor.ignore(bytes),
+        }
+    }
+
+    /// Reads the frames, each preceded by its size, up to an empty one.
+    fn read_frames(decompressor: &mut dyn Decompress, output_filename: Option<&str>)
+        -> Result<(), DecompressionError>
+    {
+        let mut output = output_filename
+            .map(File::create)
+            .transpose()
+            .map_err(|_| DecompressionError::FileCreationError)?;
+
+        loop
+        {
+            let frame_size = bytes_to_u64(decompressor.decompress_bytes_to_memory(8)?) as usize;
+            match (frame_size, output.as_mut())
+            {
+                (0, _) => return Ok(()),
+                (_, Some(output)) => decompressor.decompress_bytes_to_writer(output, frame_size)?,
+                (_, None) => decompressor.ignore(frame_size)?,
+            }
+        }
+    }
+
+    fn extract_entries(&mut self, selection: &PathSelection, output_directory: &str,
+                       canonical_output_directory: &Path, unselected_link_targets: &HashSet<String>,
+                       pending_targets: &mut HashMap<String, String>, chunk_store: &mut Option<ChunkStore>)
+        -> Result<ExtractionReport, DecompressionError>
+    {
+        let mut report = ExtractionReport::default();
+
+        // Output paths of the files extracted so far, by their paths in the archive.
+        let mut extracted_files: HashMap<String, String> = HashMap::new();
+
+        for (path, size) in &self.archive_info
+        {
+            let output_path = self.get_output_path(path, size.is_none(), selection, output_directory)?;
+
+            let bytes = match (size, output_path.as_ref())
+            {
+                (None, None) => continue,
+
+                (None, Some(output_path)) =>              // directory
                 {
-                    if let Some(bytes) = size
+                    self.check_output_path(output_path, canonical_output_directory)?;
+
+                    // An existing directory is merged with the extracted one.
+                    if fs::symlink_metadata(output_path).is_ok_and(|metadata| metadata.is_dir())
                     {
-                        self.decompressor.ignore(*bytes as usize)?;
+                        continue;
                     }
+
+                    create_dir(output_path)
+                        .map_err(|_| DecompressionError::Other)?;
                     continue;
                 }
 
-                Some(path_to_extract) => // This path is a path to be extracted.
+                (Some(_), _) if self.framed_paths.contains(path) => None,
+                (Some(bytes), _) => Some(*bytes as usize),
+            };
+
+            if let Some(target) = self.hard_links.get(path) // hard link or duplicate, no data of its own
+            {
+                let output_path = match output_path
                 {
-                    let superpath_to_be_stripped = get_superpath(path_to_extract);
+                    Some(output_path) => output_path,
+                    None => continue,
+                };
 
-                    let path_stripped = path.strip_prefix(&superpath_to_be_stripped)
-                        .expect("Bad path stripping.")
-                        .to_string();
-                    let output_path = format!("{}/{}", output_directory, path_stripped);
+                if !extracted_files.contains_key(target) && !pending_targets.contains_key(target)
+                {
+                    report.unlinked_paths.push(output_path);
+                    continue;
+                }
 
-                    if Path::new(&output_path).exists()
-                    {
-                        continue;
-                    }
+                let output_path = match self.resolve_existing_path(path, output_path, canonical_output_directory,
+                                                                   &mut report)?
+                {
+                    Some(output_path) => output_path,
+                    None => continue,
+                };
+                self.check_output_path(&output_path, canonical_output_directory)?;
+
+                // The first path linking to an unselected file gets its data.
+                if let Some(tmp_file_path) = pending_targets.remove(target)
+                {
+                    Self::move_pending_target(&tmp_file_path, &output_path)?;
+                    self.set_modification_time(target, &output_path);
+                    extracted_files.insert(target.clone(), output_path);
+                    continue;
+                }
+
+                let target_output_path = &extracted_files[target];
+
+                // Duplicates are separate files which only happened to have the same content.
+                if self.duplicates.contains(path)
+                {
+                    fs::copy(target_output_path, &output_path)
+                        .map_err(|_| DecompressionError::FileCreationError)?;
+                    self.set_modification_time(path, &output_path);
+                }
+                else
+                {
+                    Self::create_hard_link(target_output_path, &output_path)?;
+                }
+
+                // Other paths may still point to this one, e.g. a hard link to a duplicate.
+                extracted_files.insert(path.clone(), output_path);
+                continue;
+            }
 
-                    match size
+            let output_path = match output_path
+            {
+                Some(output_path) => self.resolve_existing_path(path, output_path, canonical_output_directory,
+                                                                &mut report)?,
+                None => None,
+            };
+
+            match output_path
+            {
+                Some(output_path) =>
+                {
+                    self.check_output_path(&output_path, canonical_output_directory)?;
+                    Self::read_file_data(self.decompressor.as_mut().unwrap().as_mut(),
+                                         self.chunk_lists.get(path), bytes, Some(&output_path), chunk_store)?;
+                    self.set_modification_time(path, &output_path);
+                    extracted_files.insert(path.clone(), output_path);
+                }
+
+                // Not selected, but needed by a selected hard link or duplicate.
+                None if unselected_link_targets.contains(path) =>
+                {
+                    let FileInfo
                     {
-                        None => create_dir(&output_path)    // directory
-                            .map_err(|_| DecompressionError::Other)?,
+                        handle: _,
+                        path: tmp_file_path
+                    }
+                        = create_tmp_file(".unarch")
+                        .ok_or(DecompressionError::FileCreationError)?;
 
-                        Some(bytes) =>                     // regular file
-                            self.decompressor.decompress_bytes_to_file(&output_path, *bytes as usize)?,
-                    };
+                    pending_targets.insert(path.clone(), tmp_file_path.clone());
+                    Self::read_file_data(self.decompressor.as_mut().unwrap().as_mut(),
+                                         self.chunk_lists.get(path), bytes, Some(&tmp_file_path), chunk_store)?;
                 }
+
+                None => Self::read_file_data(self.decompressor.as_mut().unwrap().as_mut(),
+                                             self.chunk_lists.get(path), bytes, None, chunk_store)?,
             }
         }
 
-        Ok(())
+        Ok(report)
+    }
+}
+
+impl Extractor // modification
+{
+    /// Works out the content of the archive with the new paths added and the given archive paths deleted.
+    pub fn prepare_repacking(mut self, paths_to_add: Vec<String>, paths_to_delete: Vec<String>,
+                             changed_files: &ChangedFiles) -> Result<Repacking, String>
+    {
+        let directory_infos = std::mem::take(&mut self.directory_infos);
+        Repacking::new(directory_infos, paths_to_add, paths_to_delete, changed_files)
+    }
+
+    /// Writes the packed content of the modified archive, reading the old data from this one,
+    /// which must not have been extracted from.
+    pub fn write_repacked(mut self, repacking: &Repacking, output: &mut dyn Write, changed_files: &ChangedFiles)
+        -> Result<(), String>
+    {
+        let decompressor = self.decompressor.as_mut()
+            .ok_or(DecompressionError::PasswordRequired.to_string())?;
+
+        repacking.write(decompressor.as_mut(), output, changed_files)
     }
 }
 
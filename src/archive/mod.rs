@@ -1,4 +1,6 @@
 mod archive_header;
+pub mod chunking;
 pub mod directory_info;
 pub mod pack;
 pub mod extractor;
+pub mod volumes;
@@ -1,12 +1,25 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs;
 use std::fs::File;
-use walkdir::WalkDir;
+use std::io;
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::sync::Mutex;
 use crate::archive::archive_header::ArchiveHeader;
-use crate::archive::directory_info::DirectoryInfo;
+use crate::archive::chunking::ChunkList;
+use crate::archive::directory_info::{DirectoryInfo, StoredFile, check_path_collisions, deduplicate,
+                                     get_default_archive_root, get_modification_time, split_files_into_chunks};
+use crate::compress::Decompress;
+use crate::encrypt::Encryption;
+use crate::io_utils::{FileInfo, bytes_to_u64, create_tmp_file, remove_tmp_file};
 use crate::io_utils::byte_writer::ByteWriter;
+use crate::io_utils::path_filter::PathFilter;
+use crate::io_utils::path_utils::{is_path_within, normalize_relative_path};
 use crate::io_utils::universal_reader::UniversalReader;
 
-fn save_file_to_archive(file_path: &str, output: &mut ByteWriter) -> Result<(), String>
+const COPY_CHUNK_SIZE: usize = 1 << 20;
+const FRAME_SIZE: usize = 1 << 16;
+
+fn copy_file_to_archive<W: Write>(file_path: &str, output: &mut ByteWriter<W>) -> Result<(), String>
 {
     let input_file = File::open(file_path)
         .map_err(|_| format!("Could not open file {}", file_path))?;
@@ -14,6 +27,7 @@ fn save_file_to_archive(file_path: &str, output: &mut ByteWriter) -> Result<(),
     let mut reader = UniversalReader::new(input_file);
 
     while let Some(byte) = reader.read_byte()
+        .map_err(|_| format!("Could not read file {}", file_path))?
     {
         output.write_byte(byte);
     }
@@ -21,34 +35,746 @@ fn save_file_to_archive(file_path: &str, output: &mut ByteWriter) -> Result<(),
     Ok(())
 }
 
-pub fn pack(input_paths: Vec<String>, output_file: File) -> Result<(), String>
+/// Reads exactly the scanned size of the file, so the data always matches the header.
+/// What is missing, e.g. of a file which shrank or disappeared, reads as zeros.
+/// A file which disappeared is packed so only if the changes are to be reported.
+fn open_scanned_file(file: &StoredFile, changed_files: &ChangedFiles) -> Result<impl Read, String>
+{
+    let input: Box<dyn Read> = match File::open(&file.source_path)
+    {
+        Ok(input_file) => Box::new(input_file),
+
+        Err(err) if err.kind() == ErrorKind::NotFound =>
+        {
+            changed_files.add(&file.source_path)?;
+            Box::new(io::empty())
+        }
+
+        Err(_) => return Err(format!("Could not open file {}", file.source_path)),
+    };
+
+    let input = input.take(file.size)
+        .chain(io::repeat(0))
+        .take(file.size);
+
+    Ok(input)
+}
+
+/// Whether the file still has the size and the modification time it had when scanned.
+fn is_unchanged(file: &StoredFile) -> bool
+{
+    fs::metadata(&file.source_path)
+        .is_ok_and(|metadata| metadata.len() == file.size && get_modification_time(&metadata) == file.modified)
+}
+
+fn save_file_to_archive<W: Write>(file: &StoredFile, changed_files: &ChangedFiles, output: &mut ByteWriter<W>)
+    -> Result<(), String>
+{
+    let mut reader = UniversalReader::new(open_scanned_file(file, changed_files)?);
+
+    while let Some(byte) = reader.read_byte()
+        .map_err(|_| format!("Could not read file {}", file.source_path))?
+    {
+        output.write_byte(byte);
+    }
+
+    Ok(())
+}
+
+/// Saves only the chunks appearing for the first time in the archive.
+fn save_chunks_to_archive<W: Write>(file: &StoredFile, chunks: &ChunkList, next_chunk_id: &mut u64,
+                                    changed_files: &ChangedFiles, output: &mut ByteWriter<W>)
+    -> Result<(), String>
+{
+    let mut reader = BufReader::new(open_scanned_file(file, changed_files)?);
+
+    for &(chunk_id, size) in chunks
+    {
+        let mut chunk = vec![0; size as usize];
+        reader.read_exact(&mut chunk)
+            .map_err(|_| format!("Could not read file {}", file.source_path))?;
+
+        if chunk_id == *next_chunk_id
+        {
+            *next_chunk_id += 1;
+            for byte in chunk
+            {
+                output.write_byte(byte);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Saves the streamed data in frames, each preceded by its size, up to an empty one.
+fn save_stream_to_archive<W: Write>(streamed_input: &StreamedInput, output: &mut ByteWriter<W>)
+    -> Result<(), String>
 {
-    let all_directory_infos: Vec<DirectoryInfo> = input_paths.iter()
-        .map(|path| DirectoryInfo::new(path))
+    let mut reader = streamed_input.reader.lock().unwrap()
+        .take()
+        .ok_or("The streamed input can be read only once.")?;
+
+    loop
+    {
+        let mut frame = Vec::with_capacity(FRAME_SIZE);
+        reader.by_ref().take(FRAME_SIZE as u64).read_to_end(&mut frame)
+            .map_err(|_| format!("Could not read {}", streamed_input.archive_path))?;
+
+        for byte in (frame.len() as u64).to_be_bytes().into_iter().chain(frame.iter().copied())
+        {
+            output.write_byte(byte);
+        }
+
+        if frame.is_empty()
+        {
+            return Ok(());
+        }
+    }
+}
+
+/// Reads the data of an old entry part by part. The data stored in frames (of size None)
+/// is read with the sizes of the frames, so it can be copied as it is.
+fn read_archive_data(archive_data: &mut dyn Decompress, size: Option<u64>,
+                     use_part: &mut dyn FnMut(Vec<u8>) -> Result<(), String>) -> Result<(), String>
+{
+    let size = match size
+    {
+        Some(size) => size,
+        None => return read_archive_frames(archive_data, use_part),
+    };
+
+    let mut bytes_left = size as usize;
+    while bytes_left > 0
+    {
+        let chunk_size = bytes_left.min(COPY_CHUNK_SIZE);
+        let chunk = archive_data.decompress_bytes_to_memory(chunk_size)
+            .map_err(|err| err.to_string())?;
+
+        use_part(chunk)?;
+        bytes_left -= chunk_size;
+    }
+
+    Ok(())
+}
+
+fn read_archive_frames(archive_data: &mut dyn Decompress,
+                       use_part: &mut dyn FnMut(Vec<u8>) -> Result<(), String>) -> Result<(), String>
+{
+    loop
+    {
+        let frame_size_bytes = archive_data.decompress_bytes_to_memory(8)
+            .map_err(|err| err.to_string())?;
+        let frame_size = bytes_to_u64(frame_size_bytes.clone());
+        use_part(frame_size_bytes)?;
+
+        if frame_size == 0
+        {
+            return Ok(());
+        }
+
+        read_archive_data(archive_data, Some(frame_size), use_part)?;
+    }
+}
+
+fn write_header<W: Write>(directory_infos: &[DirectoryInfo], output_writer: &mut ByteWriter<W>)
+    -> Result<Vec<u8>, String>
+{
+    let header_bytes = get_archive_header(directory_infos)?;
+    for &byte in &header_bytes
+    {
+        output_writer.write_byte(byte);
+    }
+
+    Ok(header_bytes)
+}
+
+#[derive(Default)]
+pub struct PackingOptions
+{
+    pub encryption: Option<Encryption>,
+
+    /// If given, the archive is split into volumes of at most that many bytes.
+    pub volume_size: Option<u64>,
+
+    /// Split big files into chunks and store the repeated chunks once.
+    pub chunking: bool,
+
+    /// Glob patterns of the archive paths to pack. If empty, everything is packed.
+    pub include_patterns: Vec<String>,
+
+    /// Glob patterns of the archive paths to skip, even if included.
+    pub exclude_patterns: Vec<String>,
+
+    /// Skip the paths ignored by .gitignore, .ignore and the global git excludes.
+    pub respect_ignore_files: bool,
+
+    /// The archive paths of the inputs, by their paths on the disk, e.g. "/etc/nginx" as "server1/nginx".
+    /// By default an input is stored under its own name.
+    pub archive_paths: HashMap<String, String>,
+
+    /// The inputs are the exact list of the entries to pack, kept in the given order.
+    /// The directories are not walked then.
+    pub exact_paths: bool,
+
+    /// What to do with the files which change or disappear before they are packed.
+    pub changed_file_policy: ChangedFilePolicy,
+
+    /// Data of unknown size, e.g. stdin, packed as one more file. It can be read only once,
+    /// so it cannot be packed by Huffman coding, which reads the data twice.
+    pub streamed_input: Option<StreamedInput>,
+}
+
+/// A stream packed as a file as it is read, in frames, without being stored first.
+pub struct StreamedInput
+{
+    pub archive_path: String,
+    reader: Mutex<Option<Box<dyn Read + Send>>>,
+}
+
+impl StreamedInput
+{
+    pub fn new(archive_path: String, reader: Box<dyn Read + Send>) -> StreamedInput
+    {
+        StreamedInput
+        {
+            archive_path,
+            reader: Mutex::new(Some(reader)),
+        }
+    }
+}
+
+/// The files are scanned for the header first and their data is read later,
+/// so a file may change or disappear in between.
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum ChangedFilePolicy
+{
+    /// Stop packing with an error.
+    #[default]
+    Fail,
+
+    /// Scan the inputs and pack them anew, a few times at most.
+    /// An archive written to a stream or packing a streamed input cannot be packed anew, so it fails then.
+    Retry,
+
+    /// Pack the file as it is now, cut or padded with zeros to the scanned size, and report it.
+    /// Huffman coding reads the files twice, so a file changing between the readings is packed anew.
+    Warn,
+}
+
+/// The files which changed after the scan, collected while the packed data is written,
+/// maybe more than once.
+pub struct ChangedFiles
+{
+    policy: ChangedFilePolicy,
+    paths: Mutex<BTreeSet<String>>,
+}
+
+impl ChangedFiles
+{
+    pub fn new(policy: ChangedFilePolicy) -> ChangedFiles
+    {
+        ChangedFiles
+        {
+            policy,
+            paths: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    /// Notes the changed file. Packing goes on only if the changes are to be reported.
+    pub fn add(&self, path: &str) -> Result<(), String>
+    {
+        self.paths.lock().unwrap()
+            .insert(path.to_string());
+
+        match self.policy
+        {
+            ChangedFilePolicy::Warn => Ok(()),
+            _ => Err(format!("File {} changed while packing.", path)),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.paths.lock().unwrap()
+            .is_empty()
+    }
+
+    pub fn get_paths(&self) -> Vec<String>
+    {
+        self.paths.lock().unwrap()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    pub fn into_paths(self) -> Vec<String>
+    {
+        self.paths.into_inner().unwrap()
+            .into_iter()
+            .collect()
+    }
+}
+
+#[derive(Default)]
+pub struct PackingReport
+{
+    pub duplicate_files_count: usize,
+    pub saved_bytes: u64,
+
+    /// The files which changed while packing, if they were to be reported.
+    pub changed_files: Vec<String>,
+}
+
+/// Saves the files to the archive in the header order. Now the full paths are needed.
+fn save_files_to_archive<W: Write>(directory_infos: &[DirectoryInfo], output_writer: &mut ByteWriter<W>,
+                                   changed_files: &ChangedFiles, streamed_input: Option<&StreamedInput>)
+    -> Result<(), String>
+{
+    let mut next_chunk_id = 0;
+
+    for directory_info in directory_infos
+    {
+        for file in directory_info.get_stored_files()
+        {
+            if file.framed
+            {
+                let streamed_input = streamed_input
+                    .ok_or("The streamed input is missing.")?;
+                save_stream_to_archive(streamed_input, output_writer)?;
+                continue;
+            }
+
+            match &file.chunks
+            {
+                Some(chunks) => save_chunks_to_archive(&file, chunks, &mut next_chunk_id, changed_files,
+                                                       output_writer)?,
+                None => save_file_to_archive(&file, changed_files, output_writer)?,
+            }
+
+            if !is_unchanged(&file)
+            {
+                changed_files.add(&file.source_path)?;
+            }
+        }
+    }
+
+    // A duplicate is stored as the data of its original, as scanned, so it must not have changed since.
+    for file in directory_infos.iter().flat_map(|info| info.get_duplicate_files())
+    {
+        if !is_unchanged(&file)
+        {
+            changed_files.add(&file.source_path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the archive paths of the inputs, in their order.
+fn get_archive_roots(input_paths: &[String], archive_paths: &HashMap<String, String>)
+    -> Result<Vec<String>, String>
+{
+    let normalize = |path: &str| path.replace('\\', "/").trim_end_matches('/').to_string();
+
+    let archive_paths: HashMap<String, &String> = archive_paths.iter()
+        .map(|(input_path, archive_path)| (normalize(input_path), archive_path))
+        .collect();
+
+    let normalized_input_paths: Vec<String> = input_paths.iter()
+        .map(|input_path| normalize(input_path))
         .collect();
 
-    let archive_header = ArchiveHeader::new(all_directory_infos)
+    if let Some(unknown_path) = archive_paths.keys().find(|path| !normalized_input_paths.contains(path))
+    {
+        return Err(format!("Path {} is not being packed.", unknown_path));
+    }
+
+    input_paths.iter()
+        .zip(&normalized_input_paths)
+        .map(|(input_path, normalized_input_path)| match archive_paths.get(normalized_input_path)
+        {
+            Some(archive_path) => check_archive_path(archive_path),
+            None => Ok(get_default_archive_root(input_path)),
+        })
+        .collect()
+}
+
+/// Returns the normalized archive path given for an input. It must stay inside the archive.
+fn check_archive_path(archive_path: &str) -> Result<String, String>
+{
+    normalize_relative_path(archive_path.replace('\\', "/").trim_end_matches('/'))
+        .filter(|normalized_path| !normalized_path.is_empty())
+        .ok_or(format!("Invalid archive path {}.", archive_path))
+}
+
+/// Decides what goes to the archive. Returns the header entries and the report of the deduplication.
+/// The paths disappearing during the scan are noted in the changed files.
+pub fn prepare_packing(input_paths: Vec<String>, options: &PackingOptions, changed_files: &ChangedFiles)
+    -> Result<(Vec<DirectoryInfo>, PackingReport), String>
+{
+    let path_filter = PathFilter::new(&options.include_patterns, &options.exclude_patterns)?;
+
+    let mut hard_link_targets = HashMap::new();
+    let mut all_directory_infos = match options.exact_paths
+    {
+        true if !options.archive_paths.is_empty() =>
+            return Err("Archive paths cannot be given for an exact list of paths.".to_string()),
+
+        true => vec![DirectoryInfo::from_path_list(&input_paths, &path_filter, &mut hard_link_targets,
+                                                   changed_files)?],
+
+        false =>
+        {
+            let archive_roots = get_archive_roots(&input_paths, &options.archive_paths)?;
+
+            let mut directory_infos: Vec<DirectoryInfo> = input_paths.iter()
+                .zip(&archive_roots)
+                .map(|(path, archive_root)| DirectoryInfo::new(path, archive_root, &path_filter,
+                                                                options.respect_ignore_files, &mut hard_link_targets,
+                                                                changed_files))
+                .collect::<Result<_, _>>()?;
+
+            // The directories of the inputs themselves need no parent directory added in their place.
+            let mut added_directories: HashSet<String> = directory_infos.iter()
+                .flat_map(|info| info.get_paths_and_sizes())
+                .filter(|(_, size)| size.is_none())
+                .map(|(path, _)| path)
+                .collect();
+            for directory_info in &mut directory_infos
+            {
+                directory_info.add_parent_directories(&mut added_directories);
+            }
+
+            directory_infos
+        }
+    };
+
+    if let Some(streamed_input) = &options.streamed_input
+    {
+        let archive_path = check_archive_path(&streamed_input.archive_path)?;
+        let mut streamed_info = DirectoryInfo::from_streamed_input(archive_path);
+
+        let mut added_directories: HashSet<String> = all_directory_infos.iter()
+            .flat_map(|info| info.get_paths_and_sizes())
+            .filter(|(_, size)| size.is_none())
+            .map(|(path, _)| path)
+            .collect();
+        streamed_info.add_parent_directories(&mut added_directories);
+
+        all_directory_infos.push(streamed_info);
+    }
+
+    check_path_collisions(&all_directory_infos)?;
+
+    let mut saved_bytes = deduplicate(&mut all_directory_infos)?;
+    if options.chunking
+    {
+        saved_bytes += split_files_into_chunks(&mut all_directory_infos)?;
+    }
+
+    let report = PackingReport
+    {
+        duplicate_files_count: all_directory_infos.iter()
+            .map(|info| info.get_duplicates().len())
+            .sum(),
+        saved_bytes,
+        changed_files: vec![],
+    };
+
+    Ok((all_directory_infos, report))
+}
+
+/// Writes the header and the data of the files. Returns the archive header.
+pub fn write_packed(directory_infos: &[DirectoryInfo], output: impl Write, changed_files: &ChangedFiles,
+                    streamed_input: Option<&StreamedInput>) -> Result<Vec<u8>, String>
+{
+    let mut output_writer = ByteWriter::new(output)?;
+    let header_bytes = write_header(directory_infos, &mut output_writer)?;
+    save_files_to_archive(directory_infos, &mut output_writer, changed_files, streamed_input)?;
+
+    Ok(header_bytes)
+}
+
+/// Returns the archive header written at the beginning of the packed data.
+pub fn get_archive_header(directory_infos: &[DirectoryInfo]) -> Result<Vec<u8>, String>
+{
+    let archive_header = ArchiveHeader::new(directory_infos)
         .map_err(|_| "Could not create archive header.")?;
 
-    let mut output_writer = ByteWriter::new(output_file)?;
+    Ok(archive_header.to_bytes())
+}
+
+/// Returns the files whose data is stored, in the archive order. The size is None for the data in frames.
+fn get_stored_paths_and_sizes(directory_infos: &[DirectoryInfo]) -> Vec<(String, Option<u64>)>
+{
+    let hard_links: HashSet<String> = directory_infos.iter()
+        .flat_map(|info| info.get_data_holders())
+        .map(|(link, _)| link)
+        .collect();
+
+    let framed_paths: HashSet<String> = directory_infos.iter()
+        .flat_map(|info| info.get_framed_paths())
+        .collect();
+
+    directory_infos.iter()
+        .flat_map(|info| info.get_paths_and_sizes())
+        .filter(|(path, _)| !hard_links.contains(path))
+        .filter_map(|(path, size)| size.map(|size| match framed_paths.contains(&path)
+        {
+            true => (path, None),
+            false => (path, Some(size)),
+        }))
+        .collect()
+}
+
+/// Decides which entries of the old archive are dropped and which of the new ones are not needed.
+/// A path being added replaces the old entry, unless it is the same directory
+/// or a file of the same size and modification time.
+fn get_replaced_entries(old_directory_infos: &[DirectoryInfo], new_directory_infos: &[DirectoryInfo],
+                        paths_to_delete: &[String]) -> (HashSet<String>, HashSet<String>)
+{
+    let old_entries: Vec<(String, Option<u64>)> = old_directory_infos.iter()
+        .flat_map(|info| info.get_paths_and_sizes())
+        .collect();
+    let old_sizes: HashMap<String, Option<u64>> = old_entries.iter().cloned().collect();
 
-    for byte in archive_header.to_bytes()
+    let get_links = |infos: &[DirectoryInfo]| -> HashSet<String>
     {
-        output_writer.write_byte(byte);
+        infos.iter()
+            .flat_map(|info| info.get_data_holders())
+            .map(|(link, _)| link)
+            .collect()
+    };
+    let get_times = |infos: &[DirectoryInfo]| -> HashMap<String, u64>
+    {
+        infos.iter()
+            .flat_map(|info| info.get_modification_times())
+            .collect()
+    };
+
+    let (old_links, new_links) = (get_links(old_directory_infos), get_links(new_directory_infos));
+    let (old_times, new_times) = (get_times(old_directory_infos), get_times(new_directory_infos));
+
+    let mut removed_old_paths: HashSet<String> = old_entries.iter()
+        .map(|(path, _)| path)
+        .filter(|path| paths_to_delete.iter().any(|path_to_delete| is_path_within(path_to_delete, path)))
+        .cloned()
+        .collect();
+
+    let mut unneeded_new_paths = HashSet::new();
+
+    for info in new_directory_infos
+    {
+        for (path, size) in info.get_paths_and_sizes()
+        {
+            let old_size = match old_sizes.get(&path)
+            {
+                Some(old_size) if !removed_old_paths.contains(&path) => *old_size,
+                _ => continue,
+            };
+
+            let same_directory = size.is_none() && old_size.is_none();
+            let same_file = size.is_some() && size == old_size
+                && !old_links.contains(&path) && !new_links.contains(&path)
+                && new_times.contains_key(&path) && new_times.get(&path) == old_times.get(&path);
+
+            if same_directory || same_file
+            {
+                unneeded_new_paths.insert(path);
+                continue;
+            }
+
+            // A directory replaced with a file takes its content away too.
+            removed_old_paths.extend(old_entries.iter()
+                .map(|(old_path, _)| old_path)
+                .filter(|old_path| is_path_within(&path, old_path))
+                .cloned());
+        }
+    }
+
+    (removed_old_paths, unneeded_new_paths)
+}
+
+/// A removed file may still hold the data of hard links or duplicates that stay in the archive.
+/// Then the first of them takes the data over. Returns the map (new holder, removed file).
+fn pass_on_hard_link_data(old_directory_infos: &mut [DirectoryInfo], removed_paths: &HashSet<String>)
+    -> HashMap<String, String>
+{
+    let mut new_holders: HashMap<String, String> = HashMap::new();
+
+    let hard_links: Vec<(String, String)> = old_directory_infos.iter()
+        .flat_map(|info| info.get_data_holders())
+        .collect();
+
+    for (link, target) in hard_links
+    {
+        if removed_paths.contains(&link) || !removed_paths.contains(&target)
+            || new_holders.values().any(|removed_target| *removed_target == target)
+        {
+            continue;
+        }
+
+        for info in old_directory_infos.iter_mut()
+        {
+            info.redirect_data_holder(&target, &link);
+        }
+        new_holders.insert(link, target);
     }
 
-    // Save the files to the archive. Now the full paths are needed.
-    for input_path in input_paths
+    new_holders
+}
+
+/// Writes the data of the old entries in the new header order.
+/// The data read ahead of its place is kept in temporary files meanwhile.
+fn copy_old_data(old_stored_files: Vec<(String, Option<u64>)>, new_stored_files: Vec<(String, Option<u64>)>,
+                 data_sources: &HashMap<String, String>, archive_data: &mut dyn Decompress,
+                 output_writer: &mut ByteWriter<impl Write>, pending_data: &mut HashMap<String, String>)
+    -> Result<(), String>
+{
+    let get_source = |path: &String| data_sources.get(path).unwrap_or(path).clone();
+
+    let needed_sources: HashSet<String> = new_stored_files.iter()
+        .map(|(path, _)| get_source(path))
+        .collect();
+
+    let mut old_stored_files = old_stored_files.into_iter();
+
+    for (path, _) in &new_stored_files
     {
-        for path in WalkDir::new(input_path)
+        let source = get_source(path);
+
+        if let Some(pending_file_path) = pending_data.remove(&source)
         {
-            let path = path.unwrap().path().to_str().unwrap().to_string();
-            if !fs::metadata(&path).unwrap().is_dir()
+            let result = copy_file_to_archive(&pending_file_path, output_writer);
+            let _ = remove_tmp_file(&pending_file_path);
+            result?;
+            continue;
+        }
+
+        loop
+        {
+            let (old_path, old_size) = old_stored_files.next()
+                .ok_or("The archive data is incomplete.")?;
+
+            if old_path == source
+            {
+                read_archive_data(archive_data, old_size, &mut |part|
+                {
+                    for byte in part
+                    {
+                        output_writer.write_byte(byte);
+                    }
+                    Ok(())
+                })?;
+                break;
+            }
+
+            if !needed_sources.contains(&old_path)
             {
-                save_file_to_archive(&path, &mut output_writer)?;
+                read_archive_data(archive_data, old_size, &mut |_| Ok(()))?;
+                continue;
             }
+
+            let FileInfo
+            {
+                handle: mut pending_file,
+                path: pending_file_path
+            }
+                = create_tmp_file(".unarch")
+                .ok_or("Could not create a temporary file while archiving.")?;
+
+            pending_data.insert(old_path, pending_file_path.clone());
+            read_archive_data(archive_data, old_size, &mut |part| pending_file.write_all(&part)
+                .map_err(|_| format!("Could not write the temporary file {}.", pending_file_path)))?;
         }
     }
 
     Ok(())
 }
+
+/// The entries of an archive with the new paths added and the given archive paths deleted,
+/// and where the data of the old entries comes from.
+pub struct Repacking
+{
+    directory_infos: Vec<DirectoryInfo>,
+    old_directories_count: usize,
+    old_stored_files: Vec<(String, Option<u64>)>,
+    new_stored_files: Vec<(String, Option<u64>)>,
+    data_sources: HashMap<String, String>,
+}
+
+impl Repacking
+{
+    pub fn new(old_directory_infos: Vec<DirectoryInfo>, paths_to_add: Vec<String>, paths_to_delete: Vec<String>,
+               changed_files: &ChangedFiles) -> Result<Repacking, String>
+    {
+        let mut old_directory_infos = old_directory_infos;
+        let old_stored_files = get_stored_paths_and_sizes(&old_directory_infos);
+
+        let mut hard_link_targets = HashMap::new();
+        let mut new_directory_infos: Vec<DirectoryInfo> = paths_to_add.iter()
+            .map(|path| DirectoryInfo::new(path, &get_default_archive_root(path), &PathFilter::default(), false,
+                                           &mut hard_link_targets, changed_files))
+            .collect::<Result<_, _>>()?;
+
+        let (removed_old_paths, unneeded_new_paths) =
+            get_replaced_entries(&old_directory_infos, &new_directory_infos, &paths_to_delete);
+
+        let data_sources = pass_on_hard_link_data(&mut old_directory_infos, &removed_old_paths);
+
+        for info in &mut old_directory_infos
+        {
+            info.remove_entries(&removed_old_paths);
+        }
+        for info in &mut new_directory_infos
+        {
+            info.remove_entries(&unneeded_new_paths);
+        }
+        deduplicate(&mut new_directory_infos)?;
+
+        let new_stored_files = get_stored_paths_and_sizes(&old_directory_infos);
+
+        let old_directories_count = old_directory_infos.len();
+        let directory_infos: Vec<DirectoryInfo> = old_directory_infos.into_iter()
+            .chain(new_directory_infos)
+            .collect();
+
+        Ok(Repacking
+        {
+            directory_infos,
+            old_directories_count,
+            old_stored_files,
+            new_stored_files,
+            data_sources,
+        })
+    }
+
+    pub fn get_directory_infos(&self) -> &[DirectoryInfo]
+    {
+        &self.directory_infos
+    }
+
+    /// Writes the packed new archive. The data of the old entries is read from the decompressor,
+    /// which must be right past the old header. It may be written again from the old archive opened anew.
+    pub fn write(&self, archive_data: &mut dyn Decompress, output: impl Write, changed_files: &ChangedFiles)
+        -> Result<(), String>
+    {
+        let mut output_writer = ByteWriter::new(output)?;
+        write_header(&self.directory_infos, &mut output_writer)?;
+
+        let mut pending_data = HashMap::new();
+        let result = copy_old_data(self.old_stored_files.clone(), self.new_stored_files.clone(),
+                                   &self.data_sources, archive_data, &mut output_writer, &mut pending_data);
+
+        for pending_file_path in pending_data.values()
+        {
+            let _ = remove_tmp_file(pending_file_path);
+        }
+        result?;
+
+        // The new files go after the old ones.
+        save_files_to_archive(&self.directory_infos[self.old_directories_count..], &mut output_writer,
+                              changed_files, None)
+    }
+}
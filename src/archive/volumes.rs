@@ -0,0 +1,402 @@
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Take, Write};
+use std::path::Path;
+
+use rand::RngCore;
+
+use crate::io_utils::{VOLUME_SIGNATURE, FileInfo, bytes_to_u64, create_sibling_tmp_file, persist_tmp_file,
+                      remove_tmp_file};
+use crate::compress::DecompressionError;
+
+
+const SIGNATURE_SIZE: usize = 3;
+
+/// Signature, volume set id, volume number, volumes count and data size.
+pub const VOLUME_HEADER_SIZE: u64 = (SIGNATURE_SIZE + 8 + 4 + 4 + 8) as u64;
+
+struct VolumeHeader
+{
+    set_id: u64,
+    number: u32,
+    count: u32,
+    data_size: u64,
+}
+
+impl VolumeHeader
+{
+    fn to_bytes(&self) -> Vec<u8>
+    {
+        [
+            VOLUME_SIGNATURE.to_be_bytes()[8 - SIGNATURE_SIZE..].to_vec(),
+            self.set_id.to_be_bytes().to_vec(),
+            self.number.to_be_bytes().to_vec(),
+            self.count.to_be_bytes().to_vec(),
+            self.data_size.to_be_bytes().to_vec(),
+        ].concat()
+    }
+
+    fn read(volume_file: &mut File) -> Option<VolumeHeader>
+    {
+        let mut bytes = vec![0; VOLUME_HEADER_SIZE as usize];
+        volume_file.read_exact(&mut bytes).ok()?;
+
+        if bytes[..SIGNATURE_SIZE] != VOLUME_SIGNATURE.to_be_bytes()[8 - SIGNATURE_SIZE..]
+        {
+            return None;
+        }
+
+        let set_id = bytes_to_u64(bytes[3..11].to_vec());
+        let number = u32::from_be_bytes(bytes[11..15].try_into().unwrap());
+        let count = u32::from_be_bytes(bytes[15..19].try_into().unwrap());
+        let data_size = bytes_to_u64(bytes[19..27].to_vec());
+
+        Some(VolumeHeader
+        {
+            set_id,
+            number,
+            count,
+            data_size,
+        })
+    }
+}
+
+/// Volumes are named "archive.xca.001", "archive.xca.002" and so on.
+pub fn get_volume_path(archive_filename: &str, number: u32) -> String
+{
+    format!("{}.{:03}", archive_filename, number)
+}
+
+/// Returns the archive filename for the path of any of its volumes.
+fn get_volume_set_name(volume_path: &str) -> Option<&str>
+{
+    let (archive_filename, number) = volume_path.rsplit_once('.')?;
+    match !number.is_empty() && number.chars().all(|c| c.is_ascii_digit())
+    {
+        true => Some(archive_filename),
+        false => None,
+    }
+}
+
+/// Writes the archive to volumes of at most volume_size bytes each as it is written,
+/// starting the next volume when one is full. The volumes go to temporary files next to their paths.
+/// Their count is known only at the end, so finish completes their headers and renames them to their paths.
+pub struct VolumeWriter
+{
+    archive_filename: String,
+    set_id: u64,
+    // The size of the data in a full volume.
+    data_size: u64,
+    volume: Option<File>,
+    volume_data_size: u64,
+    written_volume_paths: Vec<String>,
+}
+
+impl VolumeWriter
+{
+    pub fn new(archive_filename: &str, volume_size: u64) -> Result<VolumeWriter, String>
+    {
+        if volume_size <= VOLUME_HEADER_SIZE
+        {
+            return Err(format!("The volume size must be greater than {} bytes.", VOLUME_HEADER_SIZE));
+        }
+
+        Ok(VolumeWriter
+        {
+            archive_filename: archive_filename.to_string(),
+            set_id: rand::rng().next_u64(),
+            data_size: volume_size - VOLUME_HEADER_SIZE,
+            volume: None,
+            volume_data_size: 0,
+            written_volume_paths: vec![],
+        })
+    }
+
+    /// The count and the data size in the header are filled in by finish.
+    fn start_next_volume(&mut self) -> io::Result<()>
+    {
+        let number = u32::try_from(self.written_volume_paths.len() + 1)
+            .map_err(|_| io::Error::other("Too many volumes."))?;
+        let volume_path = get_volume_path(&self.archive_filename, number);
+
+        if Path::new(&volume_path).exists()
+        {
+            return Err(io::Error::new(ErrorKind::AlreadyExists, format!("Path {} already exists.", volume_path)));
+        }
+
+        let FileInfo
+        {
+            handle: mut volume,
+            path: written_volume_path
+        }
+            = create_sibling_tmp_file(&volume_path, ".xca")
+            .ok_or(io::Error::other(format!("Could not create the volume {}.", volume_path)))?;
+        self.written_volume_paths.push(written_volume_path);
+
+        let header = VolumeHeader
+        {
+            set_id: self.set_id,
+            number,
+            count: 0,
+            data_size: 0,
+        };
+        volume.write_all(&header.to_bytes())?;
+
+        self.volume = Some(volume);
+        self.volume_data_size = 0;
+        Ok(())
+    }
+
+    /// Removes the volumes written so far, so the archive can be written anew.
+    pub fn restart(&mut self)
+    {
+        self.volume = None;
+        self.volume_data_size = 0;
+
+        for written_volume_path in self.written_volume_paths.drain(..)
+        {
+            let _ = remove_tmp_file(&written_volume_path);
+        }
+    }
+
+    /// Completes the headers of the volumes and renames them to their paths,
+    /// only once all of them are written. Returns the paths of the volumes.
+    pub fn finish(mut self) -> Result<Vec<String>, String>
+    {
+        // Even an empty archive has a volume.
+        if self.volume.is_none()
+        {
+            self.start_next_volume()
+                .map_err(|err| err.to_string())?;
+        }
+        self.volume = None;
+
+        let written_volume_paths = std::mem::take(&mut self.written_volume_paths);
+        let volume_paths: Vec<String> = (1..=written_volume_paths.len() as u32)
+            .map(|number| get_volume_path(&self.archive_filename, number))
+            .collect();
+
+        let result = self.complete_volumes(&written_volume_paths, &volume_paths);
+
+        // After a failed rename some volumes are in place already.
+        if result.is_err()
+        {
+            for (written_volume_path, volume_path) in written_volume_paths.iter().zip(&volume_paths)
+            {
+                let _ = remove_tmp_file(written_volume_path);
+                let _ = fs::remove_file(volume_path);
+            }
+        }
+
+        result.map(|_| volume_paths)
+    }
+
+    fn complete_volumes(&self, written_volume_paths: &[String], volume_paths: &[String]) -> Result<(), String>
+    {
+        let count = written_volume_paths.len() as u32;
+
+        for ((number, written_volume_path), volume_path) in (1..=count).zip(written_volume_paths).zip(volume_paths)
+        {
+            let header = VolumeHeader
+            {
+                set_id: self.set_id,
+                number,
+                count,
+                data_size: match number == count
+                {
+                    true => self.volume_data_size,
+                    false => self.data_size,
+                },
+            };
+
+            OpenOptions::new()
+                .write(true)
+                .open(written_volume_path)
+                .and_then(|mut volume| volume.write_all(&header.to_bytes()))
+                .map_err(|_| format!("Could not write the volume {}.", volume_path))?;
+        }
+
+        for (written_volume_path, volume_path) in written_volume_paths.iter().zip(volume_paths)
+        {
+            persist_tmp_file(written_volume_path, volume_path)
+                .map_err(|_| format!("Could not save the volume {}.", volume_path))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Write for VolumeWriter
+{
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize>
+    {
+        if buffer.is_empty()
+        {
+            return Ok(0);
+        }
+
+        if self.volume.is_none() || self.volume_data_size == self.data_size
+        {
+            self.start_next_volume()?;
+        }
+
+        let space_left = self.data_size - self.volume_data_size;
+        let count = buffer.len().min(space_left.try_into().unwrap_or(usize::MAX));
+
+        let written = self.volume.as_mut().unwrap()
+            .write(&buffer[..count])?;
+        self.volume_data_size += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+        match &mut self.volume
+        {
+            Some(volume) => volume.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for VolumeWriter
+{
+    /// The volumes of an unfinished archive are removed.
+    fn drop(&mut self)
+    {
+        self.restart();
+    }
+}
+
+/// Reads the volume set starting with the given first volume as one archive, volume after volume.
+/// All the volumes are checked when it is opened, so a missing or a wrong one is reported at once.
+pub struct VolumeReader
+{
+    volume_paths: Vec<String>,
+    data_sizes: Vec<u64>,
+    next_volume_index: usize,
+    volume: Option<Take<File>>,
+}
+
+impl VolumeReader
+{
+    pub fn new(first_volume_path: &str) -> Result<VolumeReader, DecompressionError>
+    {
+        let archive_filename = get_volume_set_name(first_volume_path)
+            .ok_or(DecompressionError::BadFormat)?;
+
+        let mut volume_file = File::open(first_volume_path)
+            .map_err(|_| DecompressionError::FileOpenError)?;
+        let first_header = VolumeHeader::read(&mut volume_file)
+            .ok_or(DecompressionError::BadFormat)?;
+
+        if first_header.number != 1
+        {
+            return Err(DecompressionError::WrongVolume(first_volume_path.to_string()));
+        }
+
+        let mut header = first_header;
+        let count = header.count;
+        let set_id = header.set_id;
+
+        let mut volume_paths = vec![];
+        let mut data_sizes = vec![];
+        for number in 1..=count
+        {
+            let volume_path = get_volume_path(archive_filename, number);
+
+            if number > 1
+            {
+                volume_file = File::open(&volume_path)
+                    .map_err(|_| DecompressionError::MissingVolume(volume_path.clone()))?;
+
+                header = VolumeHeader::read(&mut volume_file)
+                    .ok_or(DecompressionError::WrongVolume(volume_path.clone()))?;
+            }
+
+            if header.set_id != set_id || header.number != number || header.count != count
+            {
+                return Err(DecompressionError::WrongVolume(volume_path));
+            }
+
+            // The volume was cut short or something was appended to it.
+            let volume_size = volume_file.metadata()
+                .map_err(|_| DecompressionError::FileOpenError)?
+                .len();
+            if Some(volume_size) != header.data_size.checked_add(VOLUME_HEADER_SIZE)
+            {
+                return Err(DecompressionError::BadFormat);
+            }
+
+            volume_paths.push(volume_path);
+            data_sizes.push(header.data_size);
+        }
+
+        Ok(VolumeReader
+        {
+            volume_paths,
+            data_sizes,
+            next_volume_index: 0,
+            volume: None,
+        })
+    }
+
+    /// The size of the archive held by the volumes.
+    pub fn get_data_size(&self) -> u64
+    {
+        self.data_sizes.iter().sum()
+    }
+
+    /// Opens the data of the next volume. Returns false after the last one.
+    fn open_next_volume(&mut self) -> io::Result<bool>
+    {
+        let volume_path = match self.volume_paths.get(self.next_volume_index)
+        {
+            Some(volume_path) => volume_path,
+            None => return Ok(false),
+        };
+
+        let mut volume_file = File::open(volume_path)?;
+        volume_file.seek(SeekFrom::Start(VOLUME_HEADER_SIZE))?;
+
+        self.volume = Some(volume_file.take(self.data_sizes[self.next_volume_index]));
+        self.next_volume_index += 1;
+        Ok(true)
+    }
+}
+
+impl Read for VolumeReader
+{
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize>
+    {
+        if buffer.is_empty()
+        {
+            return Ok(0);
+        }
+
+        loop
+        {
+            if let Some(volume) = &mut self.volume
+            {
+                let count = volume.read(buffer)?;
+                if count > 0
+                {
+                    return Ok(count);
+                }
+
+                // The volume was cut short since it was checked.
+                if volume.limit() > 0
+                {
+                    return Err(io::Error::new(ErrorKind::UnexpectedEof, "A volume is incomplete."));
+                }
+            }
+
+            if !self.open_next_volume()?
+            {
+                return Ok(0);
+            }
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use crate::archive::extractor::Extractor;
+use crate::archive::pack::{ChangedFilePolicy, PackingOptions, PackingReport, StreamedInput};
+use crate::compress::{CompressionMethod, pack_and_compress, pack_and_compress_to_writer};
+use crate::compress::stream::{XcaDecoder, XcaEncoder};
+use crate::encrypt::{Encryption, KeySource};
+use crate::io_utils::path_utils::read_path_list;
+
+
+const USAGE: &str = "Użycie:
+    divcomp pack [--lz77] [--null] [--files-from <lista>|-] [--stdin-name <nazwa>] [--changed fail|retry|warn]
+                [--recipient <plik z kluczem>]... <archiwum>|- [ścieżki...|-]
+    divcomp extract-stream [folder] < archiwum.xca
+    divcomp compress [--lz77] < dane > dane.xcs
+    divcomp decompress < dane.xcs > dane
+Dane ze standardowego wejścia są zawsze pakowane metodą LZ77.
+Zmienna DIVCOMP_MEMORY_MB ogranicza pamięć na bufory (w MB).
+Zmienna DIVCOMP_TMPDIR wskazuje folder na pliki tymczasowe.";
+
+/// The commands run without the window.
+pub const COMMANDS: [&str; 4] = ["pack", "extract-stream", "compress", "decompress"];
+
+/// Runs the command given in the arguments (without the program name). Returns the exit code.
+pub fn run(arguments: &[String]) -> i32
+{
+    let result = match arguments.first().map(|command| command.as_str())
+    {
+        Some("pack") => pack(&arguments[1..]),
+        Some("extract-stream") => extract_stream(&arguments[1..]),
+        Some("compress") => compress(&arguments[1..]),
+        Some("decompress") if arguments.len() == 1 => decompress(),
+        _ => Err(USAGE.to_string()),
+    };
+
+    match result
+    {
+        // The filters write their data to stdout, so they print no message.
+        Ok(message) =>
+        {
+            if !message.is_empty()
+            {
+                println!("{}", message);
+            }
+            0
+        }
+
+        Err(err_msg) =>
+        {
+            eprintln!("{}", err_msg);
+            1
+        }
+    }
+}
+
+/// With "--files-from" the listed paths are packed exactly in the given order,
+/// one per line or separated with "\0" after "--null". The list "-" is read from stdin.
+/// The archive "-" is written to stdout. The path "-", or no paths at all, packs the data from stdin
+/// as one file named by "--stdin-name", always with LZ77. "--changed" tells what to do with the files changing
+/// while packing. Each "--recipient" names a file with public keys, to which the archive is encrypted.
+fn pack(arguments: &[String]) -> Result<String, String>
+{
+    let mut compression_method = CompressionMethod::HUFFMAN;
+    let mut separator = b'\n';
+    let mut list_path = None;
+    let mut stdin_name = "stdin".to_string();
+    let mut changed_file_policy = ChangedFilePolicy::Fail;
+    let mut recipient_paths = vec![];
+    let mut paths = vec![];
+
+    let mut arguments = arguments.iter();
+    while let Some(argument) = arguments.next()
+    {
+        match argument.as_str()
+        {
+            "--lz77" => compression_method = CompressionMethod::LZ77,
+            "--null" => separator = b'\0',
+            "--files-from" => list_path = Some(arguments.next().ok_or(USAGE)?.clone()),
+            "--stdin-name" => stdin_name = arguments.next().ok_or(USAGE)?.clone(),
+            "--recipient" => recipient_paths.push(arguments.next().ok_or(USAGE)?.clone()),
+            "--changed" => changed_file_policy = match arguments.next().map(|policy| policy.as_str())
+            {
+                Some("fail") => ChangedFilePolicy::Fail,
+                Some("retry") => ChangedFilePolicy::Retry,
+                Some("warn") => ChangedFilePolicy::Warn,
+                _ => return Err(USAGE.to_string()),
+            },
+            _ => paths.push(argument.clone()),
+        }
+    }
+
+    if paths.is_empty()
+    {
+        return Err(USAGE.to_string());
+    }
+    let archive_path = paths.remove(0);
+
+    let packs_stdin = paths.iter().any(|path| path == "-") || (paths.is_empty() && list_path.is_none());
+    paths.retain(|path| path != "-");
+
+    if packs_stdin && list_path.is_some()
+    {
+        return Err("Nie można spakować danych ze standardowego wejścia razem z listą ścieżek.".to_string());
+    }
+
+    let exact_paths = list_path.is_some();
+    if let Some(list_path) = list_path
+    {
+        let listed_paths = match list_path.as_str()
+        {
+            "-" => read_path_list(io::stdin().lock(), separator)?,
+            list_path => File::open(list_path)
+                .map_err(|_| format!("Nie udało się otworzyć listy {}.", list_path))
+                .and_then(|list_file| read_path_list(list_file, separator))?,
+        };
+
+        paths.extend(listed_paths);
+    }
+
+    if let Some(path) = paths.iter().find(|path| !Path::new(path).exists())
+    {
+        return Err(format!("Plik {} nie istnieje.", path));
+    }
+
+    let mut options = PackingOptions
+    {
+        exact_paths,
+        changed_file_policy,
+        ..Default::default()
+    };
+
+    if !recipient_paths.is_empty()
+    {
+        let public_keys = recipient_paths.iter()
+            .map(|recipient_path| read_public_keys(recipient_path))
+            .collect::<Result<Vec<_>, _>>()?
+            .concat();
+
+        options.encryption = Some(Encryption
+        {
+            key_source: KeySource::Recipients(public_keys),
+            encrypt_header: true,
+        });
+    }
+
+    // LZ77 packs stdin as it is read, in frames. Huffman coding reads the data twice,
+    // so it would have to save the data whole first.
+    if packs_stdin
+    {
+        compression_method = CompressionMethod::LZ77;
+        options.streamed_input = Some(StreamedInput::new(stdin_name, Box::new(io::stdin())));
+    }
+
+    match archive_path.as_str()
+    {
+        "-" => pack_and_compress_to_writer(paths, &mut io::stdout().lock(), compression_method, options)
+            .map(|report|
+            {
+                warn_about_changed_files(&report);
+                String::new()
+            }),
+        _ => pack_and_compress(paths, archive_path, compression_method, options)
+            .map(|report|
+            {
+                warn_about_changed_files(&report);
+                "Spakowano.".to_string()
+            }),
+    }
+}
+
+/// Reads the public keys from the file, one per line. The lines starting with "#" are comments.
+fn read_public_keys(recipient_path: &str) -> Result<Vec<String>, String>
+{
+    let recipients = fs::read_to_string(recipient_path)
+        .map_err(|_| format!("Nie udało się odczytać klucza publicznego {}.", recipient_path))?;
+
+    let public_keys: Vec<String> = recipients.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+        .collect();
+
+    match public_keys.is_empty()
+    {
+        true => Err(format!("Plik {} nie zawiera klucza publicznego.", recipient_path)),
+        false => Ok(public_keys),
+    }
+}
+
+/// The warnings go to stderr, so they do not mix with an archive written to stdout.
+fn warn_about_changed_files(report: &PackingReport)
+{
+    for path in &report.changed_files
+    {
+        eprintln!("Plik {} zmienił się podczas pakowania, jego zawartość może być niepełna.", path);
+    }
+}
+
+/// Extracts the whole archive read from stdin to the given directory, by default the current one.
+fn extract_stream(arguments: &[String]) -> Result<String, String>
+{
+    let output_directory = match arguments
+    {
+        [] => ".".to_string(),
+        [output_directory] => output_directory.clone(),
+        _ => return Err(USAGE.to_string()),
+    };
+
+    let report = Extractor::from_reader(io::stdin())
+        .and_then(|mut extractor| extractor.extract_all(output_directory))
+        .map_err(|err| err.to_string())?;
+
+    let mut lines = vec!["Wypakowano.".to_string()];
+    if !report.skipped_paths.is_empty()
+    {
+        lines.push("Pominięte istniejące pliki:".to_string());
+        lines.extend(report.skipped_paths);
+    }
+    if !report.unlinked_paths.is_empty()
+    {
+        lines.push("Pominięte dowiązania do niewypakowanych plików:".to_string());
+        lines.extend(report.unlinked_paths);
+    }
+
+    Ok(lines.join("\n"))
+}
+
+/// Compresses stdin to stdout.
+fn compress(arguments: &[String]) -> Result<String, String>
+{
+    let compression_method = match arguments
+    {
+        [] => CompressionMethod::HUFFMAN,
+        [method] if method == "--lz77" => CompressionMethod::LZ77,
+        _ => return Err(USAGE.to_string()),
+    };
+
+    let mut encoder = XcaEncoder::new(io::stdout().lock(), compression_method);
+
+    io::copy(&mut io::stdin().lock(), &mut encoder)
+        .and_then(|_| encoder.finish())
+        .map(|_| String::new())
+        .map_err(|err| format!("Nie udało się skompresować danych: {}", err))
+}
+
+/// Decompresses stdin to stdout.
+fn decompress() -> Result<String, String>
+{
+    let mut decoder = XcaDecoder::new(io::stdin().lock());
+
+    io::copy(&mut decoder, &mut io::stdout().lock())
+        .map(|_| String::new())
+        .map_err(|err| format!("Nie udało się zdekompresować danych: {}", err))
+}
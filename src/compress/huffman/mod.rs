@@ -1,13 +1,14 @@
 use std::collections::HashMap;
 use std::fs::File;
+use std::io::{Read, SeekFrom, Write};
 
 use crate::io_utils::bit_vector::BitVector;
 use crate::io_utils::bit_vector_writer::BitVectorWriter;
-use crate::io_utils::HUFFMAN_SIGNATURE;
+use crate::io_utils::{HUFFMAN_SIGNATURE, get_signature_bytes};
 use crate::io_utils::universal_reader::UniversalReader;
 
 use crate::compress::byte_writer::ByteWriter;
-use crate::compress::Compress;
+use crate::compress::{Compress, DATA_CHANGED_ERROR, SeekableRead, WriteInput, read_written};
 use crate::compress::Decompress;
 use crate::compress::DecompressionError;
 use crate::compress::huffman::tree::HuffmanTree;
@@ -19,53 +20,53 @@ type Dictionary = HashMap<u8, BitVector>;
 
 pub struct HuffmanCompressor;
 
-impl Compress for HuffmanCompressor
+/// Counts the bytes written into it.
+struct FrequencyCounter
+{
+    frequencies: [usize; 256],
+}
+
+impl Write for FrequencyCounter
 {
-    fn compress(&self, input_filename: &str, output_filename: &str) -> Result<(), String>
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize>
     {
-        let input = match File::open(input_filename)
+        for &byte in buf
         {
-            Ok(file) => file,
-            Err(_) => return Err(format!("Could not open file {}.", input_filename)),
-        };
+            self.frequencies[byte as usize] += 1;
+        }
 
-        let huffman_tree = HuffmanTree::new(input);
-        if huffman_tree.empty()
-        {
-            let _empty_file = File::create(output_filename)
-                .map_err(|_| format!("Could not create empty file {}.", output_filename))?;
+        Ok(buf.len())
+    }
 
-            return Ok(());
-        }
+    fn flush(&mut self) -> std::io::Result<()>
+    {
+        Ok(())
+    }
+}
 
+impl HuffmanCompressor
+{
+    /// Writes the signature, the tree and the codewords of the input.
+    /// A byte missing from the tree means the input is not the one the tree was built from.
+    fn encode(huffman_tree: &HuffmanTree, input: impl Read, output: &mut dyn Write) -> Result<(), String>
+    {
         let tree_encoding = huffman_tree.get_tree_encoding();
         let bytes_encoding = huffman_tree.get_bytes_encoding();
 
-        let mut file_writer = match BitVectorWriter::new(output_filename)
-        {
-            Some(fw) => fw,
-            None => return Err(format!("Could not create file writer for {}.", output_filename)),
-        };
+        let mut file_writer = BitVectorWriter::new(output);
 
         // Start writing to file.
         file_writer.write_bit_vector(&BitVector::from_u64(HUFFMAN_SIGNATURE));
         file_writer.write_bit_vector(&tree_encoding);
 
-
-        // Reopen the file.
-        let input = match File::open(input_filename)
-        {
-            Ok(file) => file,
-            Err(_) => return Err(format!("Could not open file {} second time.", input_filename)),
-        };
         let mut buffer = UniversalReader::new(input);
 
-
         // read byte by byte
         while let Some(byte) = buffer.read_byte()
+            .map_err(|_| "Could not read the input.".to_string())?
         {
             let codeword = bytes_encoding.get(&byte)
-                .ok_or(&format!("Could not find codeword for byte {:X}", byte))?;
+                .ok_or(DATA_CHANGED_ERROR)?;
 
             file_writer.write_bit_vector(codeword);
         }
@@ -74,30 +75,76 @@ impl Compress for HuffmanCompressor
     }
 }
 
-pub struct HuffmanDecompressor
+impl Compress for HuffmanCompressor
 {
-    file_reader: UniversalReader,
-    dictionary: Dictionary,
-}
+    /// The input is read twice, first to build the tree. An empty input gives no output.
+    fn compress_stream(&self, input: &mut dyn SeekableRead, output: &mut dyn Write) -> Result<(), String>
+    {
+        let input_start = input.stream_position()
+            .map_err(|_| "Could not read the input.".to_string())?;
 
-impl HuffmanDecompressor
-{
-    pub fn new(input_file: File) -> Result<HuffmanDecompressor, DecompressionError>
+        let huffman_tree = HuffmanTree::new(&mut *input)
+            .map_err(|_| "Could not read the input.".to_string())?;
+        if huffman_tree.empty()
+        {
+            return Ok(());
+        }
+
+        input.seek(SeekFrom::Start(input_start))
+            .map_err(|_| "Could not read the input again.".to_string())?;
+
+        Self::encode(&huffman_tree, input, output)
+    }
+
+    /// The data is written twice, first only to count the bytes.
+    fn compress_written(&self, write_input: &WriteInput, output: &mut dyn Write) -> Result<(), String>
     {
-        let input_file_size = input_file.metadata()
-            .unwrap()
-            .len() as usize;
+        let mut frequency_counter = FrequencyCounter
+        {
+            frequencies: [0; 256],
+        };
+        write_input(&mut frequency_counter)?;
+
+        let huffman_tree = HuffmanTree::from_frequencies(&frequency_counter.frequencies);
+        if huffman_tree.empty()
+        {
+            return Ok(());
+        }
 
-        if input_file_size == 0
+        read_written(write_input, |input| Self::encode(&huffman_tree, input, output))
+    }
+
+    fn decompress_to_size(&self, input: &[u8], size: usize) -> Result<Vec<u8>, DecompressionError>
+    {
+        // An empty input compresses to nothing.
+        if size == 0
         {
-            return Err(DecompressionError::BadFormat);
+            return Ok(vec![]);
         }
 
+        let input = input.strip_prefix(get_signature_bytes(HUFFMAN_SIGNATURE).as_slice())
+            .ok_or(DecompressionError::BadFormat)?;
+
+        HuffmanDecompressor::new(input)?
+            .decompress_bytes_to_memory(size)
+    }
+}
+
+pub struct HuffmanDecompressor<R: Read = File>
+{
+    file_reader: UniversalReader<R>,
+    dictionary: Dictionary,
+}
+
+impl<R: Read> HuffmanDecompressor<R>
+{
+    /// The input starts after the signature. An empty input is not a valid stream.
+    pub fn new(input_file: R) -> Result<HuffmanDecompressor<R>, DecompressionError>
+    {
         let mut file_reader = UniversalReader::new(input_file);
 
 
-        let huffman_tree = HuffmanTree::from_code(&mut file_reader)
-            .map_err(|_| DecompressionError::BadFormat)?;
+        let huffman_tree = HuffmanTree::from_code(&mut file_reader)?;
         let dictionary = huffman_tree.get_bytes_encoding();
 
         let decompressor = HuffmanDecompressor
@@ -121,7 +168,7 @@ impl HuffmanDecompressor
     (
         &mut self,
         bytes_count: usize,
-        output_filename: Option<String>,
+        output: Option<&mut dyn Write>,
         save_to_memory: bool
     )
         -> Result<Option<Vec<u8>>, DecompressionError>
@@ -134,14 +181,11 @@ impl HuffmanDecompressor
             false => None,
         };
 
-        let mut potential_file_writer = match output_filename
+        let mut potential_file_writer = match output
         {
-            Some(filename) =>
+            Some(output) =>
             {
-                let file = File::create(filename)
-                    .map_err(|_| DecompressionError::FileCreationError)?;
-
-                let writer = ByteWriter::new(file)
+                let writer = ByteWriter::new(output)
                     .map_err(|_| DecompressionError::Other)?;
 
                 Some(writer)
@@ -154,6 +198,7 @@ impl HuffmanDecompressor
         while bytes_decompressed < bytes_count
         {
             let bit = self.file_reader.read_bit()
+                .map_err(DecompressionError::from_read_error)?
                 .ok_or(DecompressionError::BadFormat)?;
             potential_codeword.push_bit(bit);
 
@@ -178,7 +223,7 @@ impl HuffmanDecompressor
     }
 }
 
-impl Decompress for HuffmanDecompressor
+impl<R: Read> Decompress for HuffmanDecompressor<R>
 {
     fn decompress_bytes_to_memory(&mut self, bytes_to_get: usize) -> Result<Vec<u8>, DecompressionError>
     {
@@ -188,9 +233,9 @@ impl Decompress for HuffmanDecompressor
         Ok(bytes.unwrap())
     }
 
-    fn decompress_bytes_to_file(&mut self, output_filename: &str, count: usize) -> Result<(), DecompressionError>
+    fn decompress_bytes_to_writer(&mut self, output: &mut dyn Write, count: usize) -> Result<(), DecompressionError>
     {
-        self.decompress_somewhere(count, Some(output_filename.to_owned()), false)?;
+        self.decompress_somewhere(count, Some(output), false)?;
 
         Ok(())
     }
@@ -1,8 +1,10 @@
 use std::cmp::Ordering;
 use std::collections::{BinaryHeap, HashMap};
-use std::fs::File;
+use std::io;
+use std::io::Read;
 use crate::io_utils::bit_vector::BitVector;
 use crate::io_utils::universal_reader::UniversalReader;
+use crate::compress::DecompressionError;
 
 #[derive(Clone, Eq, PartialEq)]
 struct Node
@@ -61,9 +63,26 @@ pub struct HuffmanTree
 
 impl HuffmanTree
 {
-    pub fn new(input: File) -> HuffmanTree
+    pub fn new(input: impl Read) -> io::Result<HuffmanTree>
     {
-        let node_vector = Self::get_flat_node_vector(input);
+        let mut frequencies = [0; 256];
+        for byte in UniversalReader::new(input)
+        {
+            frequencies[byte? as usize] += 1;
+        }
+
+        Ok(Self::from_frequencies(&frequencies))
+    }
+
+    /// Builds the tree from the counts of all the byte values, e.g. counted while the data was written.
+    pub fn from_frequencies(frequencies: &[usize; 256]) -> HuffmanTree
+    {
+        let node_vector: Vec<Node> = frequencies.iter()
+            .enumerate()
+            .filter(|&(_, &frequency)| frequency > 0)
+            .map(|(byte, &frequency)| Node::new(byte as u8, frequency))
+            .collect();
+
         if node_vector.is_empty()
         {
             return HuffmanTree {head: None};
@@ -90,7 +109,7 @@ impl HuffmanTree
         }
     }
 
-    pub fn from_code(file_reader: &mut UniversalReader) -> Result<HuffmanTree, ()>
+    pub fn from_code<R: Read>(file_reader: &mut UniversalReader<R>) -> Result<HuffmanTree, DecompressionError>
     {
         let mut head = Node::new(0, 0);
         Self::recreate_from_code_recursive(file_reader, &mut head)?;
@@ -99,14 +118,12 @@ impl HuffmanTree
         Ok(tree)
     }
 
-    fn recreate_from_code_recursive(file_reader: &mut UniversalReader, node: &mut Node)
-                                    -> Result<(), ()>
+    fn recreate_from_code_recursive<R: Read>(file_reader: &mut UniversalReader<R>, node: &mut Node)
+                                    -> Result<(), DecompressionError>
     {
-        let bit = match file_reader.read_bit()
-        {
-            Some(b) => b,
-            None => return Err(())
-        };
+        let bit = file_reader.read_bit()
+            .map_err(DecompressionError::from_read_error)?
+            .ok_or(DecompressionError::BadFormat)?;
 
         if bit == 0
         {
@@ -131,11 +148,9 @@ impl HuffmanTree
             let mut value = 0;
             for shift in (0..8).rev()
             {
-                let next_bit = match file_reader.read_bit()
-                {
-                    Some(b) => b,
-                    None => return Err(()),
-                };
+                let next_bit = file_reader.read_bit()
+                    .map_err(DecompressionError::from_read_error)?
+                    .ok_or(DecompressionError::BadFormat)?;
 
                 value |= next_bit << shift;
             }
@@ -146,28 +161,6 @@ impl HuffmanTree
         Ok(())
     }
 
-    fn get_flat_node_vector(input: File) -> Vec<Node>
-    {
-        let file_reader = UniversalReader::new(input);
-        let mut frequency_map = HashMap::new();
-
-        for byte in file_reader
-        {
-            match frequency_map.get(&byte)
-            {
-                Some(freq) => frequency_map.insert(byte, freq + 1),
-                None => frequency_map.insert(byte, 1),
-            };
-        }
-
-        let flat_node_vector: Vec<Node> = frequency_map
-            .iter().map(|(&byte, &freq)|
-        Node::new(byte, freq))
-            .collect();
-
-        flat_node_vector
-    }
-
     pub fn get_tree_encoding(&self) -> BitVector
     {
         let mut encoding = BitVector::new();
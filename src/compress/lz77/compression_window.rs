@@ -1,22 +1,25 @@
+use std::io;
+use std::io::Read;
+
 use crate::io_utils::universal_reader::UniversalReader;
 use crate::compress::lz77::{LONG_BUFFER_SIZE, SHORT_BUFFER_SIZE};
 
 
-pub struct CompressionWindow
+pub struct CompressionWindow<R: Read>
 {
     long_buffer: Vec<u8>,
     short_buffer: Vec<u8>,
-    file_reader: UniversalReader,
+    file_reader: UniversalReader<R>,
 }
 
-impl CompressionWindow
+impl<R: Read> CompressionWindow<R>
 {
-    pub fn new(mut file_reader: UniversalReader) -> CompressionWindow
+    pub fn new(mut file_reader: UniversalReader<R>) -> io::Result<CompressionWindow<R>>
     {
         let mut short_buffer = Vec::new();
         for _ in 0..SHORT_BUFFER_SIZE
         {
-            if let Some(byte) = file_reader.read_byte()
+            if let Some(byte) = file_reader.read_byte()?
             {
                 short_buffer.push(byte);
             }
@@ -26,15 +29,17 @@ impl CompressionWindow
             }
         }
 
-        CompressionWindow
+        let window = CompressionWindow
         {
             long_buffer: Vec::new(),
             short_buffer,
             file_reader,
-        }
+        };
+
+        Ok(window)
     }
 
-    fn shift_once(&mut self)
+    fn shift_once(&mut self) -> io::Result<()>
     {
         if self.long_buffer.len() == LONG_BUFFER_SIZE
         {
@@ -46,18 +51,22 @@ impl CompressionWindow
             self.long_buffer.insert(0, self.short_buffer.remove(0));
         }
 
-        if let Some(new_byte) = self.file_reader.read_byte()
+        if let Some(new_byte) = self.file_reader.read_byte()?
         {
             self.short_buffer.push(new_byte);
         }
+
+        Ok(())
     }
 
-    pub fn shift(&mut self, n: usize)
+    pub fn shift(&mut self, n: usize) -> io::Result<()>
     {
         for _ in 0..n
         {
-            self.shift_once();
+            self.shift_once()?;
         }
+
+        Ok(())
     }
 
     pub fn short_buffer_is_empty(&self) -> bool
@@ -96,11 +105,14 @@ impl CompressionWindow
                 return Some(i - (pattern.len() - 1));
             }
 
-            // i -= mismatch_shift[text[i] as usize];
-
             // Shift the pattern to the left with proper offset.
-            // Use the saturating subtraction just to be sure.
-            i = i.saturating_sub(mismatch_shift[text[i] as usize]);
+            // Always move by at least one byte, otherwise the search never ends.
+            let shift = mismatch_shift[text[i] as usize].max(1);
+            i = match i.checked_sub(shift)
+            {
+                Some(new_i) => new_i,
+                None => break,
+            };
         }
 
         None
@@ -1,14 +1,13 @@
 use std::fs::File;
+use std::io::{Read, Write};
 use crate::io_utils::byte_writer::ByteWriter;
-use crate::io_utils::LZ77_SIGNATURE;
+use crate::io_utils::{LZ77_SIGNATURE, get_signature_bytes};
 use crate::io_utils::universal_reader::UniversalReader;
 
 mod compression_window;
-mod decompression_buffer;
 
 use crate::compress::lz77::compression_window::CompressionWindow;
-use crate::compress::lz77::decompression_buffer::DecompressionBuffer;
-use crate::compress::{Compress, Decompress, DecompressionError};
+use crate::compress::{Compress, Decompress, DecompressionError, SeekableRead, WriteInput, read_written};
 
 
 const LONG_BUFFER_SIZE: usize = 1 << 16;
@@ -18,34 +17,23 @@ pub struct LZ77Compressor;
 
 impl LZ77Compressor
 {
-    fn write_usize_to_file(value: usize, byte_writer: &mut ByteWriter)
+    fn write_usize_to_file<W: Write>(value: usize, byte_writer: &mut ByteWriter<W>)
     {
         let value = value as u16;
         byte_writer.write_byte((value >> 8) as u8);
         byte_writer.write_byte((value & 0xFF) as u8);
     }
-}
 
-impl Compress for LZ77Compressor
-{
-    fn compress(&self, input_filename: &str, output_filename: &str) -> Result<(), String>
+    /// Writes the signature and the triples (offset, match size, byte after the match).
+    fn compress_reader(input: impl Read, output: &mut dyn Write) -> Result<(), String>
     {
-        let input_file = File::open(input_filename)
-            .map_err(|err| err.to_string())?;
-        let input = UniversalReader::new(input_file);
-        let mut window = CompressionWindow::new(input);
-
-        let output_file = File::create(output_filename)
-            .map_err(|_| String::from("Could not create output file for LZ77."))?;
+        let input = UniversalReader::new(input);
+        let mut window = CompressionWindow::new(input)
+            .map_err(|_| "Could not read the input.".to_string())?;
 
-        let mut output = ByteWriter::new(output_file)?;
+        let mut output = ByteWriter::new(output)?;
 
-        let signature_bytes: Vec<u8> = LZ77_SIGNATURE.to_be_bytes()
-            .into_iter()
-            .skip_while(|&byte| byte == 0)
-            .collect();
-
-        for signature_byte in signature_bytes
+        for signature_byte in get_signature_bytes(LZ77_SIGNATURE)
         {
             output.write_byte(signature_byte);
         }
@@ -64,87 +52,187 @@ impl Compress for LZ77Compressor
                 output.write_byte(byte);
             }
 
-            window.shift(match_size + 1);
+            window.shift(match_size + 1)
+                .map_err(|_| "Could not read the input.".to_string())?;
         }
 
         Ok(())
     }
 }
 
-pub struct LZ77Decompressor
+impl Compress for LZ77Compressor
 {
-    decompression_buffer: DecompressionBuffer,
-    bytes_decompressed: usize,
+    fn compress_stream(&self, input: &mut dyn SeekableRead, output: &mut dyn Write) -> Result<(), String>
+    {
+        Self::compress_reader(input, output)
+    }
+
+    fn compress_written(&self, write_input: &WriteInput, output: &mut dyn Write) -> Result<(), String>
+    {
+        read_written(write_input, |input| Self::compress_reader(input, output))
+    }
+
+    fn decompress_to_size(&self, input: &[u8], size: usize) -> Result<Vec<u8>, DecompressionError>
+    {
+        let input = input.strip_prefix(get_signature_bytes(LZ77_SIGNATURE).as_slice())
+            .ok_or(DecompressionError::BadFormat)?;
+
+        LZ77Decompressor::new(input)?
+            .decompress_bytes_to_memory(size)
+    }
 }
 
-impl LZ77Decompressor
+/// Matches refer at most this far back, so only that much of the decompressed data is kept.
+const WINDOW_SIZE: usize = LONG_BUFFER_SIZE;
+
+pub struct LZ77Decompressor<R: Read = File>
 {
-    pub fn new(input_file: File) -> Result<Self, DecompressionError>
+    input: UniversalReader<R>,
+    // The decompressed data which the next matches may refer to, ending with the data not read yet.
+    window: Vec<u8>,
+    read_position: usize,
+    input_ended: bool,
+}
+
+impl<R: Read> LZ77Decompressor<R>
+{
+    /// The input starts after the signature. It is decompressed as the data is read,
+    /// so only the window of the last decompressed bytes is kept, in the memory.
+    pub fn new(input_file: R) -> Result<LZ77Decompressor<R>, DecompressionError>
     {
-        let mut input = UniversalReader::new(input_file);
-        let mut decompression_buffer = DecompressionBuffer::new();
+        let decompressor = LZ77Decompressor
+        {
+            input: UniversalReader::new(input_file),
+            window: Vec::with_capacity(2 * WINDOW_SIZE),
+            read_position: 0,
+            input_ended: false,
+        };
+
+        Ok(decompressor)
+    }
+
+    /// None at the end of the input.
+    fn load_u16(&mut self) -> Result<Option<u16>, DecompressionError>
+    {
+        let b1 = self.input.read_byte()
+            .map_err(DecompressionError::from_read_error)?;
+        let b2 = self.input.read_byte()
+            .map_err(DecompressionError::from_read_error)?;
+
+        let value = b1.zip(b2)
+            .map(|(b1, b2)| ((b1 as u16) << 8) | (b2 as u16));
+        Ok(value)
+    }
+
+    /// Decompresses the next triple (offset, match size, byte after the match) to the window.
+    /// Returns false at the end of the input.
+    fn decompress_triple(&mut self) -> Result<bool, DecompressionError>
+    {
+        if self.input_ended
+        {
+            return Ok(false);
+        }
 
-        while let Some(offset) = Self::load_u16(&mut input)
+        let offset = match self.load_u16()?
         {
-            let offset = offset as usize;
-            let length = Self::load_u16(&mut input).unwrap() as usize;
+            Some(offset) => offset as usize,
+            None =>
+            {
+                self.input_ended = true;
+                return Ok(false);
+            }
+        };
+        let match_size = self.load_u16()?
+            .ok_or(DecompressionError::BadFormat)? as usize;
 
-            decompression_buffer.decompress_couple(offset, length)?;
+        // The offset 0 means no match. A match longer than its offset repeats itself.
+        if offset > 0
+        {
+            let match_start = self.window.len().checked_sub(offset)
+                .ok_or(DecompressionError::BadFormat)?;
 
-            match input.read_byte()
+            for position in match_start..match_start + match_size
             {
-                Some(byte_after) => decompression_buffer.push_byte(byte_after)?,
-                None => break,
-            };
+                self.window.push(self.window[position]);
+            }
         }
 
-        let decompressor = LZ77Decompressor
+        match self.input.read_byte().map_err(DecompressionError::from_read_error)?
         {
-            decompression_buffer,
-            bytes_decompressed: 0,
-        };
+            Some(byte_after) => self.window.push(byte_after),
+            None => self.input_ended = true,
+        }
 
-        Ok(decompressor)
+        Ok(true)
     }
 
-    fn load_u16(input: &mut UniversalReader) -> Option<u16>
+    /// Drops the data already read, which no match can refer to anymore.
+    fn forget_old_data(&mut self)
     {
-        let b1 = input.read_byte()?;
-        let b2 = input.read_byte()?;
+        let old_data_size = self.read_position
+            .min(self.window.len().saturating_sub(WINDOW_SIZE));
 
-        let value = ((b1 as u16) << 8) | (b2 as u16);
-        Some(value)
+        if old_data_size >= WINDOW_SIZE
+        {
+            self.window.drain(..old_data_size);
+            self.read_position -= old_data_size;
+        }
+    }
+
+    /// Passes the next decompressed bytes to the output, part by part.
+    fn decompress_somewhere(&mut self, bytes_count: usize,
+                            output: &mut dyn FnMut(&[u8]) -> Result<(), DecompressionError>)
+        -> Result<(), DecompressionError>
+    {
+        let mut bytes_left = bytes_count;
+        while bytes_left > 0
+        {
+            while self.read_position == self.window.len()
+            {
+                self.forget_old_data();
+
+                // The stream ended before.
+                if !self.decompress_triple()?
+                {
+                    return Err(DecompressionError::BadFormat);
+                }
+            }
+
+            let part_end = self.window.len().min(self.read_position + bytes_left);
+            output(&self.window[self.read_position..part_end])?;
+
+            bytes_left -= part_end - self.read_position;
+            self.read_position = part_end;
+        }
+
+        Ok(())
     }
 }
 
-impl Decompress for LZ77Decompressor
+impl<R: Read> Decompress for LZ77Decompressor<R>
 {
     fn decompress_bytes_to_memory(&mut self, bytes_to_get: usize)
         -> Result<Vec<u8>, DecompressionError>
     {
-        let range = self.bytes_decompressed..self.bytes_decompressed + bytes_to_get;
-
-        let bytes =
-            self.decompression_buffer.get_slice_of_data(range)?;
-        self.bytes_decompressed += bytes_to_get;
+        let mut bytes = vec![];
+        self.decompress_somewhere(bytes_to_get, &mut |part|
+        {
+            bytes.extend_from_slice(part);
+            Ok(())
+        })?;
 
         Ok(bytes)
     }
 
-    fn decompress_bytes_to_file(&mut self, output_filename: &str, bytes_to_get: usize)
+    fn decompress_bytes_to_writer(&mut self, output: &mut dyn Write, bytes_to_get: usize)
         -> Result<(), DecompressionError>
     {
-        let range = self.bytes_decompressed..self.bytes_decompressed + bytes_to_get;
-        self.decompression_buffer.write_bytes_to_file(range, output_filename)?;
-        self.bytes_decompressed += bytes_to_get;
-
-        Ok(())
+        self.decompress_somewhere(bytes_to_get, &mut |part| output.write_all(part)
+            .map_err(|_| DecompressionError::Other))
     }
 
     fn ignore(&mut self, bytes_count: usize) -> Result<(), DecompressionError>
     {
-        let _ignored_data = self.decompress_bytes_to_memory(bytes_count)?;
-
-        Ok(())
+        self.decompress_somewhere(bytes_count, &mut |_| Ok(()))
     }
 }
@@ -1,17 +1,35 @@
 use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
 use std::path::Path;
 use std::fmt::Display;
-use crate::io_utils::{FileInfo, create_tmp_file, byte_writer};
+use std::thread;
+use crate::io_utils::{FileInfo, create_sibling_tmp_file, persist_tmp_file, remove_stale_sibling_tmp_files,
+                      remove_tmp_file, bytes_to_u64, byte_writer};
 
-use crate::archive::pack::pack;
+use crate::archive::pack::{ChangedFilePolicy, ChangedFiles, PackingOptions, PackingReport, get_archive_header,
+                           prepare_packing, write_packed};
+use crate::archive::directory_info::DirectoryInfo;
+use crate::archive::volumes::{VolumeWriter, get_volume_path};
+use crate::archive::extractor::Extractor;
+use crate::encrypt::{DecryptionKey, Encryption, encrypt_stream};
 
 pub mod huffman;
 pub mod lz77;
+pub mod stream;
+pub mod single_file;
 
 use crate::compress::huffman::HuffmanCompressor;
 use crate::compress::lz77::LZ77Compressor;
 
 
+/// How many times the inputs are packed at most when files change while packing.
+const MAX_PACKING_ATTEMPTS: usize = 3;
+
+/// The error of compress_written when the data was not the same each time it was written.
+pub const DATA_CHANGED_ERROR: &str = "The data to compress changed while it was compressed.";
+
 #[allow(clippy::upper_case_acronyms)] // Clippy thinks HUFFMAN is an acronym.
 #[derive(Clone, Copy, PartialEq)]
 pub enum CompressionMethod
@@ -19,55 +37,429 @@ pub enum CompressionMethod
     HUFFMAN, LZ77,
 }
 
+/// An input which can be read more than once.
+pub trait SeekableRead: Read + Seek {}
+
+impl<T: Read + Seek> SeekableRead for T {}
+
+/// Writes the data to compress, e.g. packs the files on the fly. It may be called more than once
+/// and must write the same data each time.
+pub type WriteInput<'a> = dyn Fn(&mut dyn Write) -> Result<(), String> + Sync + 'a;
+
+/// Runs write_input in another thread and gives read_input what it writes, without a temporary file.
+pub fn read_written<T>(write_input: &WriteInput, read_input: impl FnOnce(&mut dyn Read) -> Result<T, String>)
+    -> Result<T, String>
+{
+    let (mut reader, mut writer) = io::pipe()
+        .map_err(|_| "Could not create a pipe.".to_string())?;
+
+    thread::scope(|scope|
+    {
+        let writing = scope.spawn(move || write_input(&mut writer));
+
+        let read_result = read_input(&mut reader);
+
+        // Let the writer finish, even if the reader stopped early.
+        let _ = io::copy(&mut reader, &mut io::sink());
+
+        writing.join()
+            .map_err(|_| "Could not write the data to compress.".to_string())??;
+
+        read_result
+    })
+}
+
 pub trait Compress
 {
-    fn compress(&self, input_filename: &str, output_filename: &str) -> Result<(), String>;
+    /// Compresses the input from its current position to the end.
+    /// The input is seekable, because Huffman coding reads it twice.
+    fn compress_stream(&self, input: &mut dyn SeekableRead, output: &mut dyn Write) -> Result<(), String>;
+
+    /// Compresses the data written by write_input, which is not saved anywhere in between.
+    fn compress_written(&self, write_input: &WriteInput, output: &mut dyn Write) -> Result<(), String>;
+
+    /// Compresses the bytes to the size of the input followed by the stream compress_stream writes.
+    /// The stream does not tell its size itself, because it may end with padding bits.
+    fn compress_bytes(&self, input: &[u8]) -> Vec<u8>
+    {
+        let mut output = (input.len() as u64).to_be_bytes().to_vec();
+        self.compress_stream(&mut Cursor::new(input), &mut output)
+            .expect("Compressing in memory does not fail.");
+
+        output
+    }
+
+    /// Decompresses the bytes made by compress_bytes.
+    fn decompress_bytes(&self, input: &[u8]) -> Result<Vec<u8>, DecompressionError>
+    {
+        let (size_bytes, stream) = input.split_at_checked(8)
+            .ok_or(DecompressionError::BadFormat)?;
+        let size = usize::try_from(bytes_to_u64(size_bytes.to_vec()))
+            .map_err(|_| DecompressionError::BadFormat)?;
+
+        self.decompress_to_size(stream, size)
+    }
+
+    /// Decompresses a stream made by compress_stream back to the given number of bytes.
+    fn decompress_to_size(&self, input: &[u8], size: usize) -> Result<Vec<u8>, DecompressionError>;
+}
+
+pub fn get_compressor(compression_method: CompressionMethod) -> Box<dyn Compress>
+{
+    match compression_method
+    {
+        CompressionMethod::HUFFMAN => Box::new(HuffmanCompressor),
+        CompressionMethod::LZ77  => Box::new(LZ77Compressor),
+    }
 }
 
 pub fn pack_and_compress
 (
     input_paths: Vec<String>,
     archive_filename: String,
-    compression_method: CompressionMethod
+    compression_method: CompressionMethod,
+    options: PackingOptions,
 )
-    -> Result<(), String>
+    -> Result<PackingReport, String>
 {
-    if Path::new(&archive_filename).exists()
+    let first_path = match options.volume_size
+    {
+        None => archive_filename.clone(),
+        Some(_) => get_volume_path(&archive_filename, 1),
+    };
+
+    if Path::new(&first_path).exists()
     {
         return Err("Path already exists.".to_string());
     }
 
+    remove_stale_sibling_tmp_files(&archive_filename);
+
+    let volume_size = match options.volume_size
+    {
+        None => return write_archive_atomically(input_paths, &archive_filename, compression_method, &options),
+        Some(volume_size) => volume_size,
+    };
+
+    // The volumes are written as the archive is, so it is not staged anywhere whole.
+    let mut volume_writer = VolumeWriter::new(&archive_filename, volume_size)?;
+    let report = write_archive(input_paths, &mut volume_writer, compression_method, &options)?;
+    volume_writer.finish()?;
+
+    Ok(report)
+}
+
+/// Writes the archive next to its path and renames it there only once it is complete,
+/// so a failure or a crash never leaves a truncated archive behind.
+fn write_archive_atomically
+(
+    input_paths: Vec<String>,
+    archive_filename: &str,
+    compression_method: CompressionMethod,
+    options: &PackingOptions,
+)
+    -> Result<PackingReport, String>
+{
     let FileInfo
     {
-        handle: tmp_file,
-        path: tmp_file_path
+        handle: mut new_archive_file,
+        path: new_archive_path
     }
-        = create_tmp_file(".unarch")
-        .ok_or("Could not create a temporary file while archiving.")?;
+        = create_sibling_tmp_file(archive_filename, ".xca")
+        .ok_or("Could not create a temporary file next to the archive.")?;
 
-    pack(input_paths, tmp_file)?;
+    let result = write_archive(input_paths, &mut new_archive_file, compression_method, options)
+        .and_then(|report| persist_tmp_file(&new_archive_path, archive_filename)
+            .map(|_| report)
+            .map_err(|_| format!("Could not save the archive {}.", archive_filename)));
 
+    if result.is_err()
+    {
+        let _ = remove_tmp_file(&new_archive_path);
+    }
 
-    let compressor: Box<dyn Compress> = match compression_method
+    result
+}
+
+/// Packs the inputs and writes the compressed archive to the output, e.g. stdout.
+/// The packed data goes straight to the compressor, without temporary files.
+/// An encrypted or split archive cannot be written this way.
+pub fn pack_and_compress_to_writer
+(
+    input_paths: Vec<String>,
+    output: &mut dyn Write,
+    compression_method: CompressionMethod,
+    options: PackingOptions,
+)
+    -> Result<PackingReport, String>
+{
+    if options.encryption.is_some() || options.volume_size.is_some()
     {
-        CompressionMethod::HUFFMAN => Box::new(HuffmanCompressor),
-        CompressionMethod::LZ77  => Box::new(LZ77Compressor),
+        return Err("An encrypted or split archive cannot be written to a stream.".to_string());
+    }
+
+    check_streamed_input(compression_method, &options)?;
+
+    let changed_files = ChangedFiles::new(options.changed_file_policy);
+    let (directory_infos, mut report) = prepare_packing(input_paths, &options, &changed_files)?;
+    let write_input = |output: &mut dyn Write| write_packed(&directory_infos, output, &changed_files,
+                                                            options.streamed_input.as_ref())
+        .map(|_| ());
+
+    let result = get_compressor(compression_method).compress_written(&write_input, output);
+    explain_data_change(result, &changed_files)?;
+
+    report.changed_files = changed_files.into_paths();
+    Ok(report)
+}
+
+/// Huffman coding cannot write the data if it differs in its two passes, e.g. because of a file
+/// changing in between, even if the changes are only to be reported. Then the changed files are named.
+fn explain_data_change<T>(result: Result<T, String>, changed_files: &ChangedFiles) -> Result<T, String>
+{
+    match result
+    {
+        Err(err) if err == DATA_CHANGED_ERROR && !changed_files.is_empty() =>
+            Err(format!("Files changed while packing: {}.", changed_files.get_paths().join(", "))),
+
+        result => result,
+    }
+}
+
+/// A streamed input is read once, so it cannot be packed by Huffman coding, which reads the data twice.
+fn check_streamed_input(compression_method: CompressionMethod, options: &PackingOptions) -> Result<(), String>
+{
+    match (&options.streamed_input, compression_method)
+    {
+        (Some(streamed_input), CompressionMethod::HUFFMAN) =>
+            Err(format!("{} can be packed only with LZ77, because Huffman coding reads the data twice.",
+                        streamed_input.archive_path)),
+
+        _ => Ok(()),
+    }
+}
+
+/// Where the archive is written: its file or its volumes.
+trait ArchiveOutput: Write
+{
+    /// Discards what was written, so the archive can be written anew.
+    fn restart(&mut self) -> io::Result<()>;
+}
+
+impl ArchiveOutput for File
+{
+    fn restart(&mut self) -> io::Result<()>
+    {
+        self.set_len(0)?;
+        self.seek(SeekFrom::Start(0))?;
+        Ok(())
+    }
+}
+
+impl ArchiveOutput for VolumeWriter
+{
+    fn restart(&mut self) -> io::Result<()>
+    {
+        VolumeWriter::restart(self);
+        Ok(())
+    }
+}
+
+/// If files change while packing and the policy says so, the archive is written anew.
+/// A streamed input cannot be read anew, so then the archive is written once.
+fn write_archive
+(
+    input_paths: Vec<String>,
+    archive_file: &mut dyn ArchiveOutput,
+    compression_method: CompressionMethod,
+    options: &PackingOptions,
+)
+    -> Result<PackingReport, String>
+{
+    check_streamed_input(compression_method, options)?;
+
+    let mut attempts_left = match options.streamed_input
+    {
+        Some(_) => 1,
+        None => MAX_PACKING_ATTEMPTS,
     };
-    let compress_result = compressor.compress(&tmp_file_path, &archive_filename);
 
-    fs::remove_file(&tmp_file_path)
-        .map_err(|_| format!("Could not remove the temporary file {}.", tmp_file_path))?;
+    loop
+    {
+        attempts_left -= 1;
+
+        let changed_files = ChangedFiles::new(options.changed_file_policy);
+        let result = write_archive_once(input_paths.clone(), archive_file, compression_method, options,
+                                        &changed_files);
+
+        // A change which made the data impossible to write is retried even if it was only to be reported.
+        let data_changed = result.as_ref().is_err_and(|err| err == DATA_CHANGED_ERROR);
+        let retry = attempts_left > 0 && match options.changed_file_policy
+        {
+            ChangedFilePolicy::Fail => false,
+            ChangedFilePolicy::Retry => result.is_err() && (data_changed || !changed_files.is_empty()),
+            ChangedFilePolicy::Warn => data_changed,
+        };
+
+        if !retry
+        {
+            return explain_data_change(result, &changed_files)
+                .map(|mut report|
+                {
+                    report.changed_files = changed_files.into_paths();
+                    report
+                });
+        }
+
+        archive_file.restart()
+            .map_err(|_| "Could not write the archive anew.".to_string())?;
+    }
+}
+
+fn write_archive_once
+(
+    input_paths: Vec<String>,
+    archive_file: &mut dyn Write,
+    compression_method: CompressionMethod,
+    options: &PackingOptions,
+    changed_files: &ChangedFiles,
+)
+    -> Result<PackingReport, String>
+{
+    let (directory_infos, report) = prepare_packing(input_paths, options, changed_files)?;
+
+    // The packed data goes straight to the compressor. Huffman coding reads it twice,
+    // first to count the byte frequencies, so the files are read twice instead of being stored.
+    let write_input = |output: &mut dyn Write| write_packed(&directory_infos, output, changed_files,
+                                                            options.streamed_input.as_ref())
+        .map(|_| ());
+
+    compress_and_encrypt(&write_input, archive_file, compression_method, &options.encryption, &directory_infos)?;
 
-    compress_result
+    Ok(report)
+}
+
+/// Compresses the packed data written by write_input to the output, encrypting it if asked to.
+/// The compressed data goes straight on to the encryptor.
+fn compress_and_encrypt
+(
+    write_input: &WriteInput,
+    output: &mut dyn Write,
+    compression_method: CompressionMethod,
+    encryption: &Option<Encryption>,
+    directory_infos: &[DirectoryInfo],
+)
+    -> Result<(), String>
+{
+    match encryption
+    {
+        None => get_compressor(compression_method).compress_written(write_input, output),
+
+        Some(encryption) =>
+        {
+            let compress = |output: &mut dyn Write| get_compressor(compression_method)
+                .compress_written(write_input, output);
+
+            let archive_header = get_archive_header(directory_infos)?;
+            read_written(&compress, |compressed|
+                encrypt_stream(compressed, output, encryption, &archive_header))
+        }
+    }
+}
+
+/// Adds the paths to an existing archive and deletes the given archive paths from it.
+/// A path already in the archive replaces the old entry, unless the file has not changed.
+/// The archive is written anew with its compression method and the given encryption,
+/// then it replaces the old one, which stays intact if anything fails.
+/// The old archive is decompressed anew for each pass of the compressor, so the packed data
+/// is not stored anywhere.
+pub fn update_archive
+(
+    archive_filename: String,
+    paths_to_add: Vec<String>,
+    paths_to_delete: Vec<String>,
+    decryption_key: Option<DecryptionKey>,
+    encryption: Option<Encryption>,
+)
+    -> Result<(), String>
+{
+    let extractor = Extractor::open(archive_filename.clone(), decryption_key.clone())
+        .map_err(|err| err.to_string())?;
+
+    if extractor.is_split()
+    {
+        return Err("Split archives cannot be modified.".to_string());
+    }
+
+    if extractor.is_chunked()
+    {
+        return Err("Archives with files split into chunks cannot be modified.".to_string());
+    }
+
+    if extractor.is_encrypted() && encryption.is_none()
+    {
+        return Err("The archive is encrypted. Choose the encryption of the modified archive.".to_string());
+    }
+
+    let compression_method = extractor.get_compression_method()
+        .ok_or(DecompressionError::PasswordRequired.to_string())?;
+
+    remove_stale_sibling_tmp_files(&archive_filename);
+
+    let changed_files = ChangedFiles::new(ChangedFilePolicy::Fail);
+    let repacking = extractor.prepare_repacking(paths_to_add, paths_to_delete, &changed_files)?;
+
+    let write_input = |output: &mut dyn Write|
+    {
+        let old_archive = Extractor::open(archive_filename.clone(), decryption_key.clone())
+            .map_err(|err| err.to_string())?;
+
+        old_archive.write_repacked(&repacking, output, &changed_files)
+    };
+
+    let FileInfo
+    {
+        handle: mut new_archive_file,
+        path: new_archive_path
+    }
+        = create_sibling_tmp_file(&archive_filename, ".xca")
+        .ok_or("Could not create a temporary file next to the archive.")?;
+
+    let result = compress_and_encrypt(&write_input, &mut new_archive_file, compression_method, &encryption,
+                                      repacking.get_directory_infos());
+    drop(new_archive_file);
+
+    let result = explain_data_change(result, &changed_files)
+        .and_then(|_| fs::metadata(&archive_filename)
+            .and_then(|metadata| fs::set_permissions(&new_archive_path, metadata.permissions()))
+            .map_err(|_| format!("Could not copy the permissions of {}.", archive_filename)))
+        .and_then(|_| persist_tmp_file(&new_archive_path, &archive_filename)
+            .map_err(|_| format!("Could not replace the archive {}.", archive_filename)));
+
+    if result.is_err()
+    {
+        let _ = remove_tmp_file(&new_archive_path);
+    }
+
+    result
 }
 
 pub trait Decompress
 {
     fn decompress_bytes_to_memory(&mut self, bytes_to_get: usize)
                                   -> Result<Vec<u8>, DecompressionError>;
-    fn decompress_bytes_to_file(&mut self, output_filename: &str, count: usize)
-                                -> Result<(), DecompressionError>;
+    fn decompress_bytes_to_writer(&mut self, output: &mut dyn Write, count: usize)
+                                  -> Result<(), DecompressionError>;
     fn ignore(&mut self, bytes_count: usize) -> Result<(), DecompressionError>;
+
+    fn decompress_bytes_to_file(&mut self, output_filename: &str, count: usize)
+                                -> Result<(), DecompressionError>
+    {
+        let mut output = File::create(output_filename)
+            .map_err(|_| DecompressionError::FileCreationError)?;
+
+        self.decompress_bytes_to_writer(&mut output, count)
+    }
 }
 
 #[derive(Debug)]
@@ -76,20 +468,60 @@ pub enum DecompressionError
     BadFormat,
     FileOpenError,
     FileCreationError,
+    UnsafePath(String),
+    PathExists(String),
+    PasswordRequired,
+    WrongKey,
+    MissingVolume(String),
+    WrongVolume(String),
+    InvalidPattern(String),
+    PathConflict(String),
+    WrongChecksum(String),
+    NotStreamable,
+    ReadError,
     Other,
 }
 
+impl DecompressionError
+{
+    /// Readers report the data which does not decode, e.g. an encrypted chunk failing its check, as invalid.
+    pub fn from_read_error(err: io::Error) -> DecompressionError
+    {
+        match err.kind()
+        {
+            io::ErrorKind::InvalidData => DecompressionError::BadFormat,
+            _ => DecompressionError::ReadError,
+        }
+    }
+}
+
 impl Display for DecompressionError
 {
     fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
     {
         let message = match self
         {
-            DecompressionError::BadFormat           => "Nieprawidłowy plik z archiwum.",
-            DecompressionError::FileOpenError       => "Nie udało się otworzyć pliku.",
-            DecompressionError::FileCreationError   => "Nie udało się utworzyć pliku.",
-            DecompressionError::Other               => "Błąd dekompresji.",
-        }.to_string();
+            DecompressionError::BadFormat           => "Nieprawidłowy plik z archiwum.".to_string(),
+            DecompressionError::FileOpenError       => "Nie udało się otworzyć pliku.".to_string(),
+            DecompressionError::FileCreationError   => "Nie udało się utworzyć pliku.".to_string(),
+            DecompressionError::UnsafePath(path)    =>
+                format!("Ścieżka {} wychodzi poza folder docelowy.", path),
+            DecompressionError::PathExists(path)    => format!("Plik {} już istnieje.", path),
+            DecompressionError::PasswordRequired    => "Archiwum jest zaszyfrowane. Podaj hasło.".to_string(),
+            DecompressionError::WrongKey            => "Nieprawidłowe hasło lub klucz.".to_string(),
+            DecompressionError::MissingVolume(path) => format!("Brak woluminu {}.", path),
+            DecompressionError::WrongVolume(path)   =>
+                format!("Plik {} nie jest kolejnym woluminem tego archiwum.", path),
+            DecompressionError::InvalidPattern(pattern) => format!("Nieprawidłowy wzorzec {}.", pattern),
+            DecompressionError::PathConflict(path)  =>
+                format!("Kilka plików z archiwum trafiłoby do {}.", path),
+            DecompressionError::WrongChecksum(path) =>
+                format!("Suma kontrolna pliku {} się nie zgadza.", path),
+            DecompressionError::NotStreamable       =>
+                "Archiwum zaszyfrowane lub podzielone na woluminy trzeba otworzyć z pliku.".to_string(),
+            DecompressionError::ReadError           => "Nie udało się odczytać archiwum.".to_string(),
+            DecompressionError::Other               => "Błąd dekompresji.".to_string(),
+        };
 
         write!(formatter, "{}", message)
     }
@@ -0,0 +1,238 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path};
+
+use sha2::{Digest, Sha256};
+
+use crate::io_utils::{HUFFMAN_SIGNATURE, LZ77_SIGNATURE, SINGLE_FILE_SIGNATURE, FileInfo, bytes_to_u64,
+                      create_sibling_tmp_file, get_signature_bytes, persist_tmp_file,
+                      remove_stale_sibling_tmp_files, remove_tmp_file};
+use crate::compress::{CompressionMethod, Decompress, DecompressionError, get_compressor};
+use crate::compress::huffman::HuffmanDecompressor;
+use crate::compress::lz77::LZ77Decompressor;
+
+
+// A single compressed file is stored as:
+// [signature][u64 name size][name][u64 original size][SHA-256 of the original data][compressed stream],
+// without the directory header of an archive.
+
+const CHECKSUM_SIZE: usize = 32;
+const MAX_NAME_SIZE: u64 = 4096;
+
+pub struct SingleFileHeader
+{
+    pub name: String,
+    pub size: u64,
+    checksum: Vec<u8>,
+}
+
+/// Passes the written data on and computes its checksum.
+struct ChecksumWriter<W: Write>
+{
+    output: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> Write for ChecksumWriter<W>
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    {
+        let bytes_count = self.output.write(buf)?;
+        self.hasher.update(&buf[..bytes_count]);
+
+        Ok(bytes_count)
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+        self.output.flush()
+    }
+}
+
+/// Compresses one file with its name, size and checksum, like gzip does.
+/// The output appears at its path only once it is complete.
+pub fn compress_file(input_path: &str, output_path: &str, compression_method: CompressionMethod)
+    -> Result<(), String>
+{
+    if !Path::new(input_path).is_file()
+    {
+        return Err(format!("{} is not a file.", input_path));
+    }
+
+    if Path::new(output_path).exists()
+    {
+        return Err("Path already exists.".to_string());
+    }
+
+    let name = Path::new(input_path).file_name()
+        .and_then(|name| name.to_str())
+        .ok_or(format!("Invalid file name {}.", input_path))?
+        .to_string();
+
+    let mut input = File::open(input_path)
+        .map_err(|_| format!("Could not open file {}.", input_path))?;
+
+    // Read the file once for the checksum and once more to compress it.
+    let mut hasher = Sha256::new();
+    let size = io::copy(&mut input, &mut hasher)
+        .and_then(|size| input.seek(SeekFrom::Start(0)).map(|_| size))
+        .map_err(|_| format!("Could not read file {}.", input_path))?;
+
+    remove_stale_sibling_tmp_files(output_path);
+
+    let FileInfo
+    {
+        handle: mut output,
+        path: tmp_output_path
+    }
+        = create_sibling_tmp_file(output_path, ".xcz")
+        .ok_or(format!("Could not create file {}.", output_path))?;
+
+    let header = [
+        get_signature_bytes(SINGLE_FILE_SIGNATURE),
+        (name.len() as u64).to_be_bytes().to_vec(),
+        name.into_bytes(),
+        size.to_be_bytes().to_vec(),
+        hasher.finalize().to_vec(),
+    ].concat();
+
+    let result = output.write_all(&header)
+        .map_err(|_| format!("Could not write to file {}.", output_path))
+        .and_then(|_| get_compressor(compression_method).compress_stream(&mut input, &mut output))
+        .and_then(|_| persist_tmp_file(&tmp_output_path, output_path)
+            .map_err(|_| format!("Could not save file {}.", output_path)));
+
+    if result.is_err()
+    {
+        let _ = remove_tmp_file(&tmp_output_path);
+    }
+
+    result
+}
+
+fn read_header(input: &mut File) -> Result<SingleFileHeader, DecompressionError>
+{
+    let mut read_bytes = |count: usize|
+    {
+        let mut bytes = vec![0; count];
+        input.read_exact(&mut bytes)
+            .map(|_| bytes)
+            .map_err(|_| DecompressionError::BadFormat)
+    };
+
+    if read_bytes(get_signature_bytes(SINGLE_FILE_SIGNATURE).len())? != get_signature_bytes(SINGLE_FILE_SIGNATURE)
+    {
+        return Err(DecompressionError::BadFormat);
+    }
+
+    let name_size = bytes_to_u64(read_bytes(8)?);
+    if name_size > MAX_NAME_SIZE
+    {
+        return Err(DecompressionError::BadFormat);
+    }
+
+    let name = String::from_utf8(read_bytes(name_size as usize)?)
+        .map_err(|_| DecompressionError::BadFormat)?;
+    let size = bytes_to_u64(read_bytes(8)?);
+    let checksum = read_bytes(CHECKSUM_SIZE)?;
+
+    Ok(SingleFileHeader
+    {
+        name,
+        size,
+        checksum,
+    })
+}
+
+pub fn is_single_file(path: &str) -> bool
+{
+    let mut signature = vec![0; get_signature_bytes(SINGLE_FILE_SIGNATURE).len()];
+
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut signature))
+        .is_ok_and(|_| signature == get_signature_bytes(SINGLE_FILE_SIGNATURE))
+}
+
+pub fn get_single_file_header(input_path: &str) -> Result<SingleFileHeader, DecompressionError>
+{
+    let mut input = File::open(input_path)
+        .map_err(|_| DecompressionError::FileOpenError)?;
+
+    read_header(&mut input)
+}
+
+/// Decompresses the file to the output directory under its original name. Returns the output path.
+/// The output file is removed if its data does not match the checksum.
+pub fn decompress_file(input_path: &str, output_directory: &str) -> Result<String, DecompressionError>
+{
+    let mut input = File::open(input_path)
+        .map_err(|_| DecompressionError::FileOpenError)?;
+
+    let header = read_header(&mut input)?;
+
+    // The name comes from the file, so it must not lead out of the output directory.
+    let mut name_components = Path::new(&header.name).components();
+    if !matches!((name_components.next(), name_components.next()), (Some(Component::Normal(_)), None))
+    {
+        return Err(DecompressionError::UnsafePath(header.name));
+    }
+
+    let output_path = format!("{}/{}", output_directory, header.name);
+    if Path::new(&output_path).exists()
+    {
+        return Err(DecompressionError::PathExists(output_path));
+    }
+
+    let output = File::create(&output_path)
+        .map_err(|_| DecompressionError::FileCreationError)?;
+
+    let mut output = ChecksumWriter
+    {
+        output,
+        hasher: Sha256::new(),
+    };
+
+    let result = decompress_data(input, &mut output, header.size)
+        .and_then(|_| match output.hasher.finalize().to_vec() == header.checksum
+        {
+            true => Ok(output_path.clone()),
+            false => Err(DecompressionError::WrongChecksum(output_path.clone())),
+        });
+
+    if result.is_err()
+    {
+        let _ = fs::remove_file(&output_path);
+    }
+
+    result
+}
+
+fn decompress_data(mut input: File, output: &mut dyn Write, size: u64) -> Result<(), DecompressionError>
+{
+    // An empty file compresses to nothing.
+    if size == 0
+    {
+        return Ok(());
+    }
+
+    let mut signature = vec![0; get_signature_bytes(HUFFMAN_SIGNATURE).len()];
+    input.read_exact(&mut signature)
+        .map_err(|_| DecompressionError::BadFormat)?;
+
+    let mut decompressor: Box<dyn Decompress> = if signature == get_signature_bytes(HUFFMAN_SIGNATURE)
+    {
+        Box::new(HuffmanDecompressor::new(input)?)
+    }
+    else if signature == get_signature_bytes(LZ77_SIGNATURE)
+    {
+        Box::new(LZ77Decompressor::new(input)?)
+    }
+    else
+    {
+        return Err(DecompressionError::BadFormat);
+    };
+
+    decompressor.decompress_bytes_to_writer(output, size as usize)
+}
@@ -0,0 +1,228 @@
+use std::io::{Error, ErrorKind, Read, Write};
+
+use crate::io_utils::{HUFFMAN_SIGNATURE, LZ77_SIGNATURE, bytes_to_u64, get_signature_bytes};
+use crate::compress::{Compress, CompressionMethod, get_compressor};
+
+
+/// The data is compressed in blocks of this size, so the encoder does not hold all of it.
+const BLOCK_SIZE: usize = 1 << 20;
+
+// The stream is a sequence of frames:
+// [u64 original size][u64 compressed size][block compressed with Huffman coding or LZ77 by compress_bytes],
+// ended with the original size 0. The compressed block repeats the original size at its beginning.
+
+/// Compresses everything written into it to the output, like the encoders of flate2.
+/// The stream is ended with finish, or when the encoder is dropped.
+pub struct XcaEncoder<W: Write>
+{
+    output: Option<W>,
+    compressor: Box<dyn Compress>,
+    block: Vec<u8>,
+}
+
+impl<W: Write> XcaEncoder<W>
+{
+    pub fn new(output: W, compression_method: CompressionMethod) -> XcaEncoder<W>
+    {
+        XcaEncoder
+        {
+            output: Some(output),
+            compressor: get_compressor(compression_method),
+            block: Vec::with_capacity(BLOCK_SIZE),
+        }
+    }
+
+    fn write_block(&mut self) -> Result<(), Error>
+    {
+        if self.block.is_empty()
+        {
+            return Ok(());
+        }
+
+        let output = self.output.as_mut()
+            .ok_or(Error::other("The stream is already finished."))?;
+
+        let compressed_block = self.compressor.compress_bytes(&self.block);
+
+        output.write_all(&(self.block.len() as u64).to_be_bytes())?;
+        output.write_all(&(compressed_block.len() as u64).to_be_bytes())?;
+        output.write_all(&compressed_block)?;
+
+        self.block.clear();
+        Ok(())
+    }
+
+    /// Writes the rest of the data and the end of the stream. Returns the output.
+    pub fn finish(mut self) -> Result<W, Error>
+    {
+        self.write_end()?;
+
+        Ok(self.output.take().unwrap())
+    }
+
+    fn write_end(&mut self) -> Result<(), Error>
+    {
+        self.write_block()?;
+
+        let output = self.output.as_mut()
+            .ok_or(Error::other("The stream is already finished."))?;
+
+        output.write_all(&0u64.to_be_bytes())?;
+        output.flush()
+    }
+}
+
+impl<W: Write> Write for XcaEncoder<W>
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error>
+    {
+        if self.block.len() == BLOCK_SIZE
+        {
+            self.write_block()?;
+        }
+
+        let bytes_count = buf.len().min(BLOCK_SIZE - self.block.len());
+        self.block.extend_from_slice(&buf[..bytes_count]);
+
+        Ok(bytes_count)
+    }
+
+    /// Compresses the data written so far as a shorter block.
+    fn flush(&mut self) -> Result<(), Error>
+    {
+        self.write_block()?;
+
+        match self.output.as_mut()
+        {
+            Some(output) => output.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<W: Write> Drop for XcaEncoder<W>
+{
+    fn drop(&mut self)
+    {
+        if self.output.is_some()
+        {
+            let _ = self.write_end();
+        }
+    }
+}
+
+/// Yields the data decompressed from a stream made by XcaEncoder.
+pub struct XcaDecoder<R: Read>
+{
+    input: R,
+    block: Vec<u8>,
+    bytes_read_from_block: usize,
+    finished: bool,
+}
+
+impl<R: Read> XcaDecoder<R>
+{
+    pub fn new(input: R) -> XcaDecoder<R>
+    {
+        XcaDecoder
+        {
+            input,
+            block: vec![],
+            bytes_read_from_block: 0,
+            finished: false,
+        }
+    }
+
+    fn read_u64(&mut self) -> Result<u64, Error>
+    {
+        let mut bytes = vec![0; 8];
+        self.input.read_exact(&mut bytes)?;
+
+        Ok(bytes_to_u64(bytes))
+    }
+
+    fn read_block(&mut self) -> Result<(), Error>
+    {
+        let size = self.read_u64()
+            .map_err(|_| Error::new(ErrorKind::UnexpectedEof, "The stream is not complete."))?;
+
+        if size == 0
+        {
+            self.finished = true;
+            self.block.clear();
+            self.bytes_read_from_block = 0;
+            return Ok(());
+        }
+
+        // The encoder never makes larger blocks.
+        if size > BLOCK_SIZE as u64
+        {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid block size."));
+        }
+
+        let compressed_size = self.read_u64()?;
+        let mut compressed_block = vec![];
+        (&mut self.input).take(compressed_size).read_to_end(&mut compressed_block)?;
+
+        if compressed_block.len() as u64 != compressed_size
+        {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "The stream is not complete."));
+        }
+
+        // The size in the block is checked first, so a forged one is never allocated.
+        let block_size_bytes = compressed_block.get(..8)
+            .ok_or(Error::new(ErrorKind::InvalidData, "Invalid block size."))?;
+        if bytes_to_u64(block_size_bytes.to_vec()) != size
+        {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid block size."));
+        }
+
+        let compressor = Self::get_block_compressor(&compressed_block[8..])
+            .ok_or(Error::new(ErrorKind::InvalidData, "Unknown compression method."))?;
+
+        self.block = compressor.decompress_bytes(&compressed_block)
+            .map_err(|err| Error::new(ErrorKind::InvalidData, err.to_string()))?;
+        self.bytes_read_from_block = 0;
+
+        Ok(())
+    }
+
+    /// Every block starts with the signature of its compression method, after its size.
+    fn get_block_compressor(compressed_block: &[u8]) -> Option<Box<dyn Compress>>
+    {
+        let compression_method =
+            if compressed_block.starts_with(&get_signature_bytes(HUFFMAN_SIGNATURE))
+        {
+            CompressionMethod::HUFFMAN
+        }
+        else if compressed_block.starts_with(&get_signature_bytes(LZ77_SIGNATURE))
+        {
+            CompressionMethod::LZ77
+        }
+        else
+        {
+            return None;
+        };
+
+        Some(get_compressor(compression_method))
+    }
+}
+
+impl<R: Read> Read for XcaDecoder<R>
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error>
+    {
+        while self.bytes_read_from_block == self.block.len() && !self.finished
+        {
+            self.read_block()?;
+        }
+
+        let block_rest = &self.block[self.bytes_read_from_block..];
+        let bytes_count = buf.len().min(block_rest.len());
+
+        buf[..bytes_count].copy_from_slice(&block_rest[..bytes_count]);
+        self.bytes_read_from_block += bytes_count;
+
+        Ok(bytes_count)
+    }
+}
@@ -0,0 +1,498 @@
+use std::io::{Read, Write};
+use std::mem::swap;
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use chacha20poly1305::aead::{Aead, Payload};
+use rand::RngCore;
+
+use crate::compress::DecompressionError;
+use crate::io_utils::{ENCRYPTED_SIGNATURE, bytes_to_u64};
+use crate::encrypt::recipients::{WRAPPED_KEY_SIZE, parse_identity, parse_public_key, unwrap_file_key,
+                                 wrap_file_key};
+
+pub mod recipients;
+
+
+const CHUNK_SIZE: usize = 1 << 16;
+const TAG_SIZE: usize = 16;
+const KEY_SIZE: usize = 32;
+const SALT_SIZE: usize = 16;
+const NONCE_PREFIX_SIZE: usize = 7;
+
+const PASSWORD_KEY: u8 = 1;
+const RECIPIENTS_KEY: u8 = 2;
+const HEADER_VISIBLE_FLAG: u8 = 1;
+
+pub enum KeySource
+{
+    Password(String),
+    /// Public keys of the recipients in the text form.
+    /// A random key is encrypted separately for each of them.
+    Recipients(Vec<String>),
+}
+
+pub struct Encryption
+{
+    pub key_source: KeySource,
+    pub encrypt_header: bool,
+}
+
+/// What is needed to decrypt an archive: the password or the content of an identity file.
+#[derive(Clone)]
+pub enum DecryptionKey
+{
+    Password(String),
+    Identity(String),
+}
+
+/// Argon2id parameters used to turn a password into a key.
+struct KeyDerivation
+{
+    salt: [u8; SALT_SIZE],
+    memory_cost: u32,
+    time_cost: u32,
+    parallelism: u32,
+}
+
+impl KeyDerivation
+{
+    fn new() -> KeyDerivation
+    {
+        let mut salt = [0; SALT_SIZE];
+        rand::rng().fill_bytes(&mut salt);
+
+        KeyDerivation
+        {
+            salt,
+            memory_cost: Params::DEFAULT_M_COST,
+            time_cost: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<KeyDerivation, DecompressionError>
+    {
+        let get_u32 = |index: usize|
+            u32::from_be_bytes(bytes[index..index + 4].try_into().unwrap());
+
+        let key_derivation = KeyDerivation
+        {
+            salt: bytes[0..SALT_SIZE].try_into().unwrap(),
+            memory_cost: get_u32(SALT_SIZE),
+            time_cost: get_u32(SALT_SIZE + 4),
+            parallelism: get_u32(SALT_SIZE + 8),
+        };
+
+        let costs_allowed = key_derivation.memory_cost <= Params::DEFAULT_M_COST * MAX_COST_FACTOR
+            && key_derivation.time_cost <= Params::DEFAULT_T_COST * MAX_COST_FACTOR
+            && key_derivation.parallelism <= Params::DEFAULT_P_COST * MAX_COST_FACTOR;
+
+        match costs_allowed
+        {
+            true => Ok(key_derivation),
+            false => Err(DecompressionError::BadFormat),
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8>
+    {
+        [
+            self.salt.to_vec(),
+            self.memory_cost.to_be_bytes().to_vec(),
+            self.time_cost.to_be_bytes().to_vec(),
+            self.parallelism.to_be_bytes().to_vec(),
+        ].concat()
+    }
+
+    fn derive_key(&self, password: &str) -> Option<Key>
+    {
+        let params = Params::new(self.memory_cost, self.time_cost, self.parallelism, Some(KEY_SIZE))
+            .ok()?;
+
+        let mut key = Key::default();
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(password.as_bytes(), &self.salt, &mut key)
+            .ok()?;
+
+        Some(key)
+    }
+}
+
+const KEY_DERIVATION_SIZE: usize = SALT_SIZE + 12;
+
+/// How many times the costs read from an archive may exceed the ones used for writing.
+/// Bigger costs would let a crafted archive exhaust the memory or the time before the password is checked.
+const MAX_COST_FACTOR: u32 = 8;
+
+/// The nonce of a chunk is its number preceded by a random prefix
+/// and followed by a flag marking the last chunk, so chunks cannot be reordered or cut off.
+fn get_chunk_nonce(nonce_prefix: &[u8], chunk_number: u32, last: bool) -> Nonce
+{
+    let nonce_bytes = [
+        nonce_prefix.to_vec(),
+        chunk_number.to_be_bytes().to_vec(),
+        vec![last as u8],
+    ].concat();
+
+    *Nonce::from_slice(&nonce_bytes)
+}
+
+/// Reads until the buffer is full or the file ends. Returns the count of bytes read.
+fn fill_buffer(file: &mut impl Read, buffer: &mut [u8]) -> std::io::Result<usize>
+{
+    let mut bytes_read = 0;
+    while bytes_read < buffer.len()
+    {
+        let count = file.read(&mut buffer[bytes_read..])?;
+        if count == 0
+        {
+            break;
+        }
+
+        bytes_read += count;
+    }
+
+    Ok(bytes_read)
+}
+
+/// Returns the key for the archive, the key method and the data needed to recreate the key:
+/// the key derivation parameters or the wrapped keys.
+fn get_key_and_key_block(key_source: &KeySource) -> Result<(Key, u8, Vec<u8>), String>
+{
+    match key_source
+    {
+        KeySource::Password(password) =>
+        {
+            let key_derivation = KeyDerivation::new();
+            let key = key_derivation.derive_key(password)
+                .ok_or("Could not derive the encryption key.")?;
+
+            Ok((key, PASSWORD_KEY, key_derivation.to_bytes()))
+        }
+
+        KeySource::Recipients(public_keys) =>
+        {
+            if public_keys.is_empty()
+            {
+                return Err("No recipients given.".to_string());
+            }
+
+            let mut key = Key::default();
+            rand::rng().fill_bytes(&mut key);
+
+            let recipients_count = u16::try_from(public_keys.len())
+                .map_err(|_| "Too many recipients.")?;
+            let mut key_block = recipients_count.to_be_bytes().to_vec();
+
+            for public_key in public_keys
+            {
+                let recipient = parse_public_key(public_key)
+                    .ok_or(format!("Invalid public key {}.", public_key))?;
+
+                key_block.extend(wrap_file_key(&key, &recipient)?);
+            }
+
+            Ok((key, RECIPIENTS_KEY, key_block))
+        }
+    }
+}
+
+/// Encrypts the compressed archive read from the input, e.g. straight from the compressor.
+/// The archive header (as written by the packer) is stored in plain text too, unless the header is to be encrypted.
+pub fn encrypt_stream(mut input: &mut dyn Read, output: &mut dyn Write, encryption: &Encryption,
+                      archive_header: &[u8]) -> Result<(), String>
+{
+    let (key, key_method, key_block) = get_key_and_key_block(&encryption.key_source)?;
+    let cipher = ChaCha20Poly1305::new(&key);
+
+    let mut nonce_prefix = [0; NONCE_PREFIX_SIZE];
+    rand::rng().fill_bytes(&mut nonce_prefix);
+
+    let flags = if encryption.encrypt_header { 0 } else { HEADER_VISIBLE_FLAG };
+
+    // Everything after the signature is authenticated together with the first chunk.
+    let mut preamble = [vec![key_method, flags], key_block, nonce_prefix.to_vec()].concat();
+    if !encryption.encrypt_header
+    {
+        preamble.extend_from_slice(archive_header);
+    }
+
+    let signature_bytes: Vec<u8> = ENCRYPTED_SIGNATURE.to_be_bytes()
+        .into_iter()
+        .skip_while(|&byte| byte == 0)
+        .collect();
+
+    let write_error = |_| "Could not write the encrypted archive.".to_string();
+    let read_error = |_| "Could not read the archive to encrypt.".to_string();
+
+    output.write_all(&signature_bytes).map_err(write_error)?;
+    output.write_all(&preamble).map_err(write_error)?;
+
+    let mut current_chunk = vec![0; CHUNK_SIZE];
+    let mut next_chunk = vec![0; CHUNK_SIZE];
+    let mut current_size = fill_buffer(&mut input, &mut current_chunk).map_err(read_error)?;
+    let mut chunk_number: u32 = 0;
+
+    loop
+    {
+        let next_size = match current_size == CHUNK_SIZE
+        {
+            true => fill_buffer(&mut input, &mut next_chunk).map_err(read_error)?,
+            false => 0,
+        };
+        let last = next_size == 0;
+
+        let payload = Payload
+        {
+            msg: &current_chunk[..current_size],
+            aad: if chunk_number == 0 { &preamble } else { &[] },
+        };
+        let nonce = get_chunk_nonce(&nonce_prefix, chunk_number, last);
+        let ciphertext = cipher.encrypt(&nonce, payload)
+            .map_err(|_| "Could not encrypt the archive.")?;
+
+        output.write_all(&ciphertext).map_err(write_error)?;
+
+        if last
+        {
+            break;
+        }
+
+        swap(&mut current_chunk, &mut next_chunk);
+        current_size = next_size;
+        chunk_number = chunk_number.checked_add(1)
+            .ok_or("The archive is too big to be encrypted.")?;
+    }
+
+    Ok(())
+}
+
+enum KeyBlock
+{
+    Password(KeyDerivation),
+    Recipients(Vec<Vec<u8>>),
+}
+
+pub struct EncryptedArchive
+{
+    archive_file: Box<dyn Read>,
+    key_block: KeyBlock,
+    nonce_prefix: Vec<u8>,
+    preamble: Vec<u8>,
+    visible_header: Option<Vec<u8>>,
+}
+
+impl EncryptedArchive
+{
+    /// Reads the encryption parameters. The signature must have been read already.
+    /// The archive size bounds the size of the visible header.
+    pub fn new(mut archive_file: Box<dyn Read>, archive_size: u64) -> Result<EncryptedArchive, DecompressionError>
+    {
+        let mut preamble = vec![];
+        let mut read_bytes = |count: usize| -> Result<Vec<u8>, DecompressionError>
+        {
+            let mut bytes = vec![0; count];
+            archive_file.read_exact(&mut bytes)
+                .map_err(|_| DecompressionError::BadFormat)?;
+
+            preamble.extend_from_slice(&bytes);
+            Ok(bytes)
+        };
+
+        let method_and_flags = read_bytes(2)?;
+        let key_block = match method_and_flags[0]
+        {
+            PASSWORD_KEY => KeyBlock::Password(KeyDerivation::from_bytes(&read_bytes(KEY_DERIVATION_SIZE)?)?),
+
+            RECIPIENTS_KEY =>
+            {
+                let recipients_count = u16::from_be_bytes(read_bytes(2)?.try_into().unwrap());
+                let wrapped_keys = (0..recipients_count)
+                    .map(|_| read_bytes(WRAPPED_KEY_SIZE))
+                    .collect::<Result<Vec<Vec<u8>>, DecompressionError>>()?;
+
+                KeyBlock::Recipients(wrapped_keys)
+            }
+
+            _ => return Err(DecompressionError::BadFormat),
+        };
+
+        let nonce_prefix = read_bytes(NONCE_PREFIX_SIZE)?;
+
+        let visible_header = if method_and_flags[1] & HEADER_VISIBLE_FLAG != 0
+        {
+            let header_size_bytes = read_bytes(8)?;
+            let header_size = bytes_to_u64(header_size_bytes.clone());
+            if header_size > archive_size
+            {
+                return Err(DecompressionError::BadFormat);
+            }
+
+            let header_data = read_bytes(header_size as usize)?;
+            Some([header_size_bytes, header_data].concat())
+        }
+        else
+        {
+            None
+        };
+
+        let encrypted_archive = EncryptedArchive
+        {
+            archive_file,
+            key_block,
+            nonce_prefix,
+            preamble,
+            visible_header,
+        };
+
+        Ok(encrypted_archive)
+    }
+
+    /// Returns the archive header stored in plain text, if the header is not encrypted.
+    pub fn get_visible_header(&self) -> Option<&Vec<u8>>
+    {
+        self.visible_header.as_ref()
+    }
+
+    fn get_key(&self, decryption_key: &DecryptionKey) -> Result<Key, DecompressionError>
+    {
+        match (&self.key_block, decryption_key)
+        {
+            (KeyBlock::Password(key_derivation), DecryptionKey::Password(password)) =>
+                key_derivation.derive_key(password)
+                    .ok_or(DecompressionError::BadFormat),
+
+            (KeyBlock::Recipients(wrapped_keys), DecryptionKey::Identity(identity)) =>
+            {
+                let secret = parse_identity(identity)
+                    .ok_or(DecompressionError::WrongKey)?;
+
+                wrapped_keys.iter()
+                    .find_map(|wrapped_key| unwrap_file_key(wrapped_key, &secret))
+                    .ok_or(DecompressionError::WrongKey)
+            }
+
+            _ => Err(DecompressionError::WrongKey),
+        }
+    }
+
+    /// Returns the decrypted archive, which is decrypted chunk by chunk while it is read.
+    /// The first chunk is decrypted at once, so a wrong password or key fails here.
+    pub fn decrypt(self, decryption_key: &DecryptionKey) -> Result<DecryptedArchive, DecompressionError>
+    {
+        let key = self.get_key(decryption_key)?;
+
+        let encrypted_chunk_size = CHUNK_SIZE + TAG_SIZE;
+        let mut decrypted_archive = DecryptedArchive
+        {
+            archive_file: self.archive_file,
+            cipher: ChaCha20Poly1305::new(&key),
+            nonce_prefix: self.nonce_prefix,
+            preamble: self.preamble,
+            current_chunk: vec![0; encrypted_chunk_size],
+            next_chunk: vec![0; encrypted_chunk_size],
+            current_size: 0,
+            chunk_number: 0,
+            plaintext: vec![],
+            bytes_read_from_plaintext: 0,
+            finished: false,
+        };
+
+        decrypted_archive.current_size = fill_buffer(&mut decrypted_archive.archive_file,
+                                                     &mut decrypted_archive.current_chunk)
+            .map_err(|_| DecompressionError::ReadError)?;
+        decrypted_archive.decrypt_next_chunk()?;
+
+        Ok(decrypted_archive)
+    }
+}
+
+pub struct DecryptedArchive
+{
+    archive_file: Box<dyn Read>,
+    cipher: ChaCha20Poly1305,
+    nonce_prefix: Vec<u8>,
+    preamble: Vec<u8>,
+    // The next chunk is read ahead to know if the current one is the last.
+    current_chunk: Vec<u8>,
+    next_chunk: Vec<u8>,
+    current_size: usize,
+    chunk_number: u32,
+    plaintext: Vec<u8>,
+    bytes_read_from_plaintext: usize,
+    finished: bool,
+}
+
+impl DecryptedArchive
+{
+    fn decrypt_next_chunk(&mut self) -> Result<(), DecompressionError>
+    {
+        let encrypted_chunk_size = CHUNK_SIZE + TAG_SIZE;
+        let next_size = match self.current_size == encrypted_chunk_size
+        {
+            true => fill_buffer(&mut self.archive_file, &mut self.next_chunk)
+                .map_err(|_| DecompressionError::ReadError)?,
+            false => 0,
+        };
+        let last = next_size == 0;
+
+        let payload = Payload
+        {
+            msg: &self.current_chunk[..self.current_size],
+            aad: if self.chunk_number == 0 { &self.preamble } else { &[] },
+        };
+        let nonce = get_chunk_nonce(&self.nonce_prefix, self.chunk_number, last);
+
+        // Failing on the first chunk most likely means a wrong password.
+        self.plaintext = self.cipher.decrypt(&nonce, payload)
+            .map_err(|_| match self.chunk_number
+            {
+                0 => DecompressionError::WrongKey,
+                _ => DecompressionError::BadFormat,
+            })?;
+        self.bytes_read_from_plaintext = 0;
+
+        if last
+        {
+            self.finished = true;
+            return Ok(());
+        }
+
+        swap(&mut self.current_chunk, &mut self.next_chunk);
+        self.current_size = next_size;
+        self.chunk_number = self.chunk_number.checked_add(1)
+            .ok_or(DecompressionError::BadFormat)?;
+
+        Ok(())
+    }
+}
+
+impl Read for DecryptedArchive
+{
+    /// A chunk failing its check is reported as invalid data.
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize>
+    {
+        while self.bytes_read_from_plaintext == self.plaintext.len()
+        {
+            if self.finished
+            {
+                return Ok(0);
+            }
+
+            self.decrypt_next_chunk()
+                .map_err(|err| match err
+                {
+                    DecompressionError::ReadError => std::io::Error::other(err.to_string()),
+                    _ => std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string()),
+                })?;
+        }
+
+        let plaintext_left = &self.plaintext[self.bytes_read_from_plaintext..];
+        let count = buffer.len().min(plaintext_left.len());
+        buffer[..count].copy_from_slice(&plaintext_left[..count]);
+        self.bytes_read_from_plaintext += count;
+
+        Ok(count)
+    }
+}
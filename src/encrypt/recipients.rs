@@ -0,0 +1,161 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use chacha20poly1305::aead::Aead;
+use hkdf::Hkdf;
+use rand::RngCore;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::encrypt::{KEY_SIZE, TAG_SIZE};
+
+
+pub const PUBLIC_KEY_PREFIX: &str = "xca-pub-";
+pub const SECRET_KEY_PREFIX: &str = "XCA-SECRET-";
+
+const WRAPPING_INFO: &[u8] = b"divcomp x25519 file key";
+
+/// Ephemeral public key followed by the encrypted file key.
+pub const WRAPPED_KEY_SIZE: usize = 32 + KEY_SIZE + TAG_SIZE;
+
+fn to_hex(bytes: &[u8]) -> String
+{
+    bytes.iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>>
+{
+    if !text.len().is_multiple_of(2) || !text.is_ascii()
+    {
+        return None;
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&text[index..index + 2], 16).ok())
+        .collect()
+}
+
+fn decode_key(text: &str, prefix: &str) -> Option<[u8; 32]>
+{
+    let key_hex = text.trim().strip_prefix(prefix)?;
+    from_hex(key_hex)?.try_into().ok()
+}
+
+fn get_random_secret() -> StaticSecret
+{
+    let mut secret_bytes = [0; 32];
+    rand::rng().fill_bytes(&mut secret_bytes);
+
+    StaticSecret::from(secret_bytes)
+}
+
+pub fn parse_public_key(text: &str) -> Option<PublicKey>
+{
+    decode_key(text, PUBLIC_KEY_PREFIX)
+        .map(PublicKey::from)
+}
+
+/// Reads the secret key from the content of an identity file.
+/// Lines starting with '#' are comments.
+pub fn parse_identity(identity: &str) -> Option<StaticSecret>
+{
+    let key_line = identity.lines()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty() && !line.starts_with('#'))?;
+
+    decode_key(key_line, SECRET_KEY_PREFIX)
+        .map(StaticSecret::from)
+}
+
+/// Returns the content of a new identity file and the public key matching it.
+fn generate_identity() -> (String, String)
+{
+    let secret = get_random_secret();
+    let public_key = format!("{}{}", PUBLIC_KEY_PREFIX, to_hex(PublicKey::from(&secret).as_bytes()));
+
+    let identity = format!("# public key: {}\n{}{}\n",
+                           public_key, SECRET_KEY_PREFIX, to_hex(secret.as_bytes()));
+
+    (identity, public_key)
+}
+
+/// Creates a new identity file readable only by its owner. Returns the matching public key.
+pub fn save_new_identity(identity_filename: &str) -> Result<String, String>
+{
+    let (identity, public_key) = generate_identity();
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    options.open(identity_filename)
+        .and_then(|mut file| file.write_all(identity.as_bytes()))
+        .map_err(|_| format!("Could not create the identity file {}.", identity_filename))?;
+
+    Ok(public_key)
+}
+
+fn get_wrapping_key(shared_secret: &[u8], ephemeral_public: &PublicKey, recipient: &PublicKey)
+    -> Key
+{
+    let salt = [ephemeral_public.as_bytes().as_slice(), recipient.as_bytes().as_slice()].concat();
+
+    let mut wrapping_key = Key::default();
+    Hkdf::<Sha256>::new(Some(&salt), shared_secret)
+        .expand(WRAPPING_INFO, &mut wrapping_key)
+        .expect("The key size is valid for HKDF.");
+
+    wrapping_key
+}
+
+/// Encrypts the file key so only the owner of the recipient's secret key can read it.
+pub fn wrap_file_key(file_key: &Key, recipient: &PublicKey) -> Result<Vec<u8>, String>
+{
+    let ephemeral_secret = get_random_secret();
+    let ephemeral_public = PublicKey::from(&ephemeral_secret);
+
+    let shared_secret = ephemeral_secret.diffie_hellman(recipient);
+    if !shared_secret.was_contributory()
+    {
+        return Err("Invalid recipient public key.".to_string());
+    }
+
+    let wrapping_key = get_wrapping_key(shared_secret.as_bytes(), &ephemeral_public, recipient);
+
+    // Every wrapping key is used only once, so the nonce may be constant.
+    let wrapped_key = ChaCha20Poly1305::new(&wrapping_key)
+        .encrypt(&Nonce::default(), file_key.as_slice())
+        .map_err(|_| "Could not encrypt the file key.")?;
+
+    Ok([ephemeral_public.as_bytes().to_vec(), wrapped_key].concat())
+}
+
+pub fn unwrap_file_key(wrapped_key: &[u8], secret: &StaticSecret) -> Option<Key>
+{
+    let ephemeral_public: [u8; 32] = wrapped_key[..32].try_into().ok()?;
+    let ephemeral_public = PublicKey::from(ephemeral_public);
+
+    let shared_secret = secret.diffie_hellman(&ephemeral_public);
+    if !shared_secret.was_contributory()
+    {
+        return None;
+    }
+
+    let wrapping_key =
+        get_wrapping_key(shared_secret.as_bytes(), &ephemeral_public, &PublicKey::from(secret));
+
+    let file_key = ChaCha20Poly1305::new(&wrapping_key)
+        .decrypt(&Nonce::default(), &wrapped_key[32..])
+        .ok()?;
+
+    Some(*Key::from_slice(&file_key))
+}
@@ -1,15 +1,24 @@
 mod util;
 use util::{MultithreadedData, load_icon};
 use std::collections::{HashMap, HashSet};
-use std::{path::Path, thread, sync::Arc};
-use crate::io_utils::path_utils::{ARCHIVE_EXTENSION, get_display_paths, sanitize_path, sanitize_output_path, is_a_subdirectory};
-use crate::archive::extractor::Extractor;
+use std::{fs, path::Path, thread, sync::{Arc, Mutex}};
+use crate::io_utils::path_utils::{ARCHIVE_EXTENSION, SINGLE_FILE_EXTENSION, get_display_paths, read_path_list,
+                                  sanitize_path, sanitize_output_path, is_a_subdirectory};
+use crate::io_utils::path_filter::PathSelection;
+use crate::io_utils::{set_memory_budget, set_tmp_directory};
+use crate::archive::extractor::{ExtractionReport, Extractor, OverwritePolicy, PathRemapping};
+use crate::archive::pack::{ChangedFilePolicy, PackingOptions};
 use crate::compress::
 {
     CompressionMethod,
     CompressionMethod::{HUFFMAN, LZ77},
+    DecompressionError,
     pack_and_compress,
+    update_archive,
 };
+use crate::compress::single_file::{compress_file, decompress_file, get_single_file_header, is_single_file};
+use crate::encrypt::{DecryptionKey, Encryption, KeySource};
+use crate::encrypt::recipients::save_new_identity;
 use eframe::egui;
 use egui::Ui;
 use egui::InnerResponse;
@@ -22,13 +31,36 @@ pub struct Gui
 
     input_archive_path: String,
     output_directory: String,
+    trusted_archive: bool,
+    overwrite_policy: OverwritePolicy,
+    strip_components: String,
+    prefix_rewrites: String,
+    flatten_paths: bool,
+    archive_password: String,
+    archive_identity: String,
+    archive_encrypted: MultithreadedData<bool>,
 
     archive_content: MultithreadedData<Vec<String>>,
     selected_archive_items: HashSet<String>,
+    selection_patterns: String,
     display_path_map: HashMap<String, String>,
 
     paths_to_pack: Vec<String>,
     output_archive_path: String,
+    packing_password: String,
+    recipients: String,
+    encrypt_header: bool,
+    volume_size: String,
+    chunking: bool,
+    archive_paths: String,
+    exact_paths: bool,
+    include_patterns: String,
+    exclude_patterns: String,
+    respect_ignore_files: bool,
+    changed_file_policy: ChangedFilePolicy,
+
+    memory_budget: String,
+    tmp_directory: String,
 
     status_display: MultithreadedData<String>,
 
@@ -44,11 +76,33 @@ impl Default for Gui
             compression_method: HUFFMAN,
             input_archive_path: String::new(),
             output_directory: String::new(),
+            trusted_archive: false,
+            overwrite_policy: OverwritePolicy::Skip,
+            strip_components: String::new(),
+            prefix_rewrites: String::new(),
+            flatten_paths: false,
+            archive_password: String::new(),
+            archive_identity: String::new(),
+            archive_encrypted: MultithreadedData::new(false),
             archive_content: MultithreadedData::new(vec![]),
             selected_archive_items: HashSet::new(),
+            selection_patterns: String::new(),
             display_path_map: HashMap::new(),
             paths_to_pack: Vec::new(),
             output_archive_path: String::new(),
+            packing_password: String::new(),
+            recipients: String::new(),
+            encrypt_header: true,
+            volume_size: String::new(),
+            chunking: false,
+            archive_paths: String::new(),
+            exact_paths: false,
+            include_patterns: String::new(),
+            exclude_patterns: String::new(),
+            respect_ignore_files: false,
+            changed_file_policy: ChangedFilePolicy::Fail,
+            memory_budget: String::new(),
+            tmp_directory: String::new(),
             status_display: MultithreadedData::new(String::new()),
             processing: false,
         }
@@ -74,6 +128,19 @@ impl Gui
     }
 }
 
+/// Parses the lines of the form "path=other path". Returns the first bad line on error.
+fn parse_path_pairs(text: &str) -> Result<Vec<(String, String)>, String>
+{
+    text.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| line.split_once('=')
+            .map(|(first_path, second_path)| (sanitize_path(&first_path.trim().to_string()),
+                                              sanitize_path(&second_path.trim().to_string())))
+            .ok_or(line.to_string()))
+        .collect()
+}
+
 impl Gui // packing
 {
     fn select_files_to_pack(&mut self)
@@ -133,11 +200,47 @@ impl Gui // packing
         }
     }
 
+    /// Replaces the paths to pack with the ones listed in a file, one per line
+    /// or separated with "\0", and packs exactly them.
+    fn load_path_list(&mut self)
+    {
+        let list_path = match FileDialog::new()
+            .set_title("Wybierz listę ścieżek")
+            .pick_file()
+        {
+            Some(list_path) => list_path,
+            None => return,
+        };
+
+        let paths = fs::read(&list_path)
+            .map_err(|_| String::from("Nie udało się otworzyć listy ścieżek."))
+            .and_then(|content|
+            {
+                let separator = match content.contains(&0)
+                {
+                    true => b'\0',
+                    false => b'\n',
+                };
+                read_path_list(content.as_slice(), separator)
+            });
+
+        match paths
+        {
+            Ok(paths) =>
+            {
+                self.paths_to_pack = paths;
+                self.exact_paths = true;
+            }
+            Err(err_msg) => self.status_display.set_content(err_msg),
+        }
+    }
+
     fn select_output_archive_path(&mut self)
     {
         if let Some(path) = FileDialog::new()
             .set_title("Wybierz lokalizację i wpisz nazwę.")
             .add_filter("Archiwum xca", &[ARCHIVE_EXTENSION])
+            .add_filter("Pojedynczy plik xcz", &[SINGLE_FILE_EXTENSION])
             .save_file()
         {
             self.output_archive_path = path.to_str().unwrap().to_string();
@@ -187,20 +290,160 @@ impl Gui // packing
         }
 
         let output_path = sanitize_output_path(&self.output_archive_path);
+        if output_path.ends_with(SINGLE_FILE_EXTENSION)
+        {
+            self.compress_single_file(input_paths, output_path);
+            return;
+        }
+
+        // The volume size is given in megabytes.
+        let volume_size = match self.volume_size.trim()
+        {
+            "" => None,
+            volume_size => match volume_size.parse::<u64>()
+            {
+                Ok(megabytes) if megabytes > 0 => Some(megabytes << 20),
+                _ =>
+                {
+                    self.status_display.set_content(String::from("Nieprawidłowy rozmiar woluminu."));
+                    return;
+                }
+            },
+        };
+
+        let archive_paths = match parse_path_pairs(&self.archive_paths)
+        {
+            Ok(archive_paths) => archive_paths.into_iter().collect(),
+            Err(line) =>
+            {
+                self.status_display.set_content(format!("Nieprawidłowa ścieżka w archiwum: {}", line));
+                return;
+            }
+        };
+
         self.status_display.set_content(String::from("Pakowanie..."));
 
         let compression_method = self.compression_method;
+        let options = PackingOptions
+        {
+            encryption: self.get_encryption(),
+            volume_size,
+            chunking: self.chunking,
+            include_patterns: self.include_patterns.split_whitespace().map(String::from).collect(),
+            exclude_patterns: self.exclude_patterns.split_whitespace().map(String::from).collect(),
+            respect_ignore_files: self.respect_ignore_files,
+            archive_paths,
+            exact_paths: self.exact_paths,
+            changed_file_policy: self.changed_file_policy,
+            streamed_input: None,
+        };
+
+        self.spawn_task(move ||
+        {
+            match pack_and_compress(input_paths, output_path, compression_method, options)
+            {
+                Ok(report) =>
+                {
+                    let mut lines = vec![match report.saved_bytes
+                    {
+                        0 => "Spakowano.".to_string(),
+                        saved_bytes => format!("Spakowano. Powtórzone pliki: {}, zaoszczędzono {} B.",
+                                               report.duplicate_files_count, saved_bytes),
+                    }];
+
+                    if !report.changed_files.is_empty()
+                    {
+                        lines.push("Pliki zmienione podczas pakowania, ich zawartość może być niepełna:".to_string());
+                        lines.extend(report.changed_files);
+                    }
+
+                    lines.join("\n")
+                }
+                Err(err_msg) => err_msg,
+            }
+        });
+    }
+
+    /// Compresses one file without the archive header, like gzip does.
+    fn compress_single_file(&mut self, input_paths: Vec<String>, output_path: String)
+    {
+        let input_path = match input_paths.as_slice()
+        {
+            [input_path] if Path::new(input_path).is_file() => input_path.clone(),
+            _ =>
+            {
+                self.status_display
+                    .set_content(String::from("Do pliku xcz można skompresować tylko jeden plik."));
+                return;
+            }
+        };
+
+        if !self.packing_password.is_empty() || !self.recipients.trim().is_empty()
+        {
+            self.status_display.set_content(String::from("Pliku xcz nie można zaszyfrować."));
+            return;
+        }
+
+        self.status_display.set_content(String::from("Kompresowanie..."));
 
+        let compression_method = self.compression_method;
         self.spawn_task(move ||
         {
-            match pack_and_compress(input_paths, output_path, compression_method)
+            match compress_file(&input_path, &output_path, compression_method)
             {
-                Ok(_) => "Spakowano.".to_string(),
+                Ok(_) => "Skompresowano.".to_string(),
                 Err(err_msg) => err_msg,
             }
         });
     }
 
+    fn get_encryption(&self) -> Option<Encryption>
+    {
+        // Public keys, one per line, take precedence over the password.
+        let recipients: Vec<String> = self.recipients.lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect();
+
+        let key_source = if !recipients.is_empty()
+        {
+            Some(KeySource::Recipients(recipients))
+        }
+        else if !self.packing_password.is_empty()
+        {
+            Some(KeySource::Password(self.packing_password.clone()))
+        }
+        else
+        {
+            None
+        };
+
+        key_source.map(|key_source| Encryption
+        {
+            key_source,
+            encrypt_header: self.encrypt_header,
+        })
+    }
+
+    fn generate_key_pair(&mut self)
+    {
+        if let Some(path) = FileDialog::new()
+            .set_title("Zapisz klucz prywatny")
+            .save_file()
+        {
+            let path = path.to_str().unwrap().to_string();
+            match save_new_identity(&path)
+            {
+                Ok(public_key) =>
+                {
+                    self.recipients.push_str(&format!("{}\n", public_key));
+                    self.status_display.set_content(format!("Klucz publiczny: {}", public_key));
+                }
+                Err(err_msg) => self.status_display.set_content(err_msg),
+            }
+        }
+    }
+
     fn packing_vertical(&mut self, ui: &mut Ui) -> InnerResponse<()>
     {
         ui.vertical(|ui|
@@ -217,6 +460,11 @@ impl Gui // packing
                 {
                     self.select_folders_to_pack();
                 }
+
+                if ui.button("Wczytaj listę ścieżek").clicked()
+                {
+                    self.load_path_list();
+                }
             });
 
             ui.label("Ścieżki do spakowania:");
@@ -226,10 +474,19 @@ impl Gui // packing
                 .max_height(600.0)
                 .show(ui, |ui| self.show_paths_to_pack(ui));
 
-            if ui.button("Wyczyść").clicked()
+            ui.horizontal(|ui|
             {
-                self.paths_to_pack.clear();
-            }
+                if ui.button("Wyczyść").clicked()
+                {
+                    self.paths_to_pack.clear();
+                }
+
+                ui.checkbox(&mut self.exact_paths, "Tylko wymienione ścieżki, bez zawartości folderów");
+            });
+
+            ui.add(egui::TextEdit::multiline(&mut self.archive_paths)
+                .desired_rows(2)
+                .hint_text("Ścieżki w archiwum, np. /etc/nginx=serwer1/nginx, po jednej w linii..."));
 
             ui.horizontal(|ui|
             {
@@ -256,6 +513,56 @@ impl Gui // packing
                     });
                 });
             });
+
+            ui.horizontal(|ui|
+            {
+                ui.add(egui::TextEdit::singleline(&mut self.packing_password)
+                    .password(true)
+                    .hint_text("Hasło (opcjonalne)..."));
+
+                ui.checkbox(&mut self.encrypt_header, "Szyfruj listę plików");
+
+                ui.add(egui::TextEdit::singleline(&mut self.volume_size)
+                    .desired_width(120.0)
+                    .hint_text("Rozmiar woluminu (MB)..."));
+
+                ui.checkbox(&mut self.chunking, "Deduplikacja fragmentów plików");
+            });
+
+            // Glob patterns separated with spaces, e.g. "*.rs" or "target/".
+            ui.horizontal(|ui|
+            {
+                ui.add(egui::TextEdit::singleline(&mut self.include_patterns)
+                    .hint_text("Uwzględnij tylko (np. *.rs src/)..."));
+
+                ui.add(egui::TextEdit::singleline(&mut self.exclude_patterns)
+                    .hint_text("Pomiń (np. target/ *.o)..."));
+
+                ui.checkbox(&mut self.respect_ignore_files, "Pomiń pliki z .gitignore");
+            });
+
+            ui.vertical(|ui|
+            {
+                ui.label("Jeśli plik zmieni się podczas pakowania:");
+                ui.horizontal(|ui|
+                {
+                    ui.radio_value(&mut self.changed_file_policy, ChangedFilePolicy::Fail, "Przerwij");
+                    ui.radio_value(&mut self.changed_file_policy, ChangedFilePolicy::Retry, "Spakuj ponownie");
+                    ui.radio_value(&mut self.changed_file_policy, ChangedFilePolicy::Warn, "Ostrzeż");
+                });
+            });
+
+            ui.horizontal(|ui|
+            {
+                ui.add(egui::TextEdit::multiline(&mut self.recipients)
+                    .desired_rows(2)
+                    .hint_text("Klucze publiczne odbiorców, po jednym w linii..."));
+
+                if ui.button("Generuj parę kluczy").clicked()
+                {
+                    self.generate_key_pair();
+                }
+            });
         })
     }
 }
@@ -266,6 +573,8 @@ impl Gui // extraction
     {
         let chosen_path = FileDialog::new()
             .add_filter("Archiwa xca", &[ARCHIVE_EXTENSION])
+            .add_filter("Pliki xcz", &[SINGLE_FILE_EXTENSION])
+            .add_filter("Pierwszy wolumin", &["001"])
             .add_filter("Wszystkie pliki", &["*"])
             .pick_file();
 
@@ -275,6 +584,7 @@ impl Gui // extraction
             let path = sanitize_path(&path);
 
             self.input_archive_path = path.clone();
+            self.forget_archive_keys();
             self.show_archive_content();
         }
     }
@@ -289,18 +599,23 @@ impl Gui // extraction
 
     fn show_packed_files_selection(&mut self, ui: &mut Ui) -> InnerResponse<()>
     {
+        // Also mark the content of the selected directories and the paths matching the patterns.
+        let selection = PathSelection::new(&self.get_chosen_paths()).ok();
+
         ui.vertical(|ui|
         {
             for path in self.archive_content.get_content().iter()
             {
-                let is_selected = self.selected_archive_items.contains(path);
+                let is_clicked = self.selected_archive_items.contains(path);
+                let is_selected = is_clicked || selection.as_ref()
+                    .is_some_and(|selection| selection.is_selected(Self::get_archive_path(path)));
                 let display_path = self.display_path_map.get(path).unwrap();
 
                 let response = ui.selectable_label(is_selected, display_path);
 
                 if response.clicked()
                 {
-                    if is_selected
+                    if is_clicked
                     {
                         // Unclick
                         self.selected_archive_items.remove(path);
@@ -315,6 +630,90 @@ impl Gui // extraction
         })
     }
 
+    fn open_extractor(input_path: String, password: &str, identity: &str)
+        -> Result<Extractor, DecompressionError>
+    {
+        if !identity.is_empty()
+        {
+            Extractor::with_identity(input_path, identity)
+        }
+        else if !password.is_empty()
+        {
+            Extractor::with_password(input_path, password)
+        }
+        else
+        {
+            Extractor::new(input_path)
+        }
+    }
+
+    fn select_identity_file(&mut self)
+    {
+        if let Some(path) = FileDialog::new()
+            .set_title("Wybierz klucz prywatny")
+            .pick_file()
+        {
+            match std::fs::read_to_string(path)
+            {
+                Ok(identity) =>
+                {
+                    self.archive_identity = identity;
+                    self.show_archive_content();
+                }
+                Err(_) => self.status_display
+                    .set_content(String::from("Nie udało się odczytać klucza prywatnego.")),
+            }
+        }
+    }
+
+    fn forget_archive_keys(&mut self)
+    {
+        self.archive_encrypted.set_content(false);
+        self.archive_password.clear();
+        self.archive_identity.clear();
+    }
+
+    fn load_archive_content(input_path: String, password: &str, identity: &str,
+                            result: &Mutex<Option<Vec<String>>>, encrypted: &Mutex<Option<bool>>)
+    {
+        // A single compressed file is shown under its original name.
+        let (content, password_needed) = if is_single_file(&input_path)
+        {
+            match get_single_file_header(&input_path)
+            {
+                Ok(header) => (format!("{} {:?}", header.name, Some(header.size)), false),
+                Err(err) => (err.to_string(), false),
+            }
+        }
+        else
+        {
+            match Self::open_extractor(input_path, password, identity)
+            {
+                Ok(extractor) => (extractor.to_string(), extractor.requires_password()),
+                Err(err) =>
+                {
+                    let password_needed = matches!(err,
+                        DecompressionError::PasswordRequired | DecompressionError::WrongKey);
+                    (err.to_string(), password_needed)
+                }
+            }
+        };
+
+        let paths: Vec<String> = content
+            .lines()
+            .map(|line| line.to_string())
+            .collect();
+
+        // Once the password worked, keep asking for it for this archive.
+        if password_needed || !password.is_empty() || !identity.is_empty()
+        {
+            *encrypted.lock().unwrap() = Some(true);
+        }
+
+        let mut result_lock = result.lock().unwrap();
+        *result_lock = Some(paths)
+    }
+
     fn show_archive_content(&mut self)
     {
         if self.processing
@@ -324,26 +723,120 @@ impl Gui // extraction
 
         self.processing = true;
         let input_path = sanitize_path(&self.input_archive_path);
+        let password = self.archive_password.clone();
+        let identity = self.archive_identity.clone();
         let result = Arc::clone(&self.archive_content.result);
+        let encrypted = Arc::clone(&self.archive_encrypted.result);
 
         thread::spawn(move ||
         {
-            let content = match Extractor::new(input_path)
+            Self::load_archive_content(input_path, &password, &identity, &result, &encrypted);
+        });
+
+        self.processing = false;
+    }
+
+    /// Removes everything after the actual path from a position of the selection menu,
+    /// e.g., "Some", "None" and all that shit.
+    fn get_archive_path(item: &str) -> &str
+    {
+        let index_to_split = item.rfind(' ').unwrap_or(item.len());
+        &item[..index_to_split]
+    }
+
+    /// The clicked positions of the selection menu and the typed patterns.
+    fn get_chosen_paths(&self) -> Vec<String>
+    {
+        self.selected_archive_items.iter()
+            .map(|item| Self::get_archive_path(item).to_string())
+            .chain(self.selection_patterns.split_whitespace().map(String::from))
+            .collect()
+    }
+
+    /// All the archive paths selected by the clicked positions and the patterns.
+    fn get_selected_archive_paths(&self) -> Result<Vec<String>, String>
+    {
+        let selection = PathSelection::new(&self.get_chosen_paths())
+            .map_err(|pattern| DecompressionError::InvalidPattern(pattern).to_string())?;
+
+        let selected_paths = self.archive_content.get_content().iter()
+            .map(|item| Self::get_archive_path(item))
+            .filter(|path| selection.is_selected(path))
+            .map(String::from)
+            .collect();
+
+        Ok(selected_paths)
+    }
+
+    /// Adds the paths to pack to the chosen archive or deletes the selected entries from it.
+    fn do_modification(&mut self, paths_to_add: Vec<String>, paths_to_delete: Vec<String>)
+    {
+        if self.processing
+        {
+            return;
+        }
+
+        let input_path = sanitize_path(&self.input_archive_path);
+        if input_path.is_empty()
+        {
+            self.status_display
+                .set_content(String::from("Podaj ścieżkę archiwum."));
+            return;
+        }
+
+        if let Some(path) = paths_to_add.iter().find(|path| !Path::new(path).exists())
+        {
+            self.status_display.set_content(format!("Plik {} nie istnieje.", path));
+            return;
+        }
+
+        let password = self.archive_password.clone();
+        let identity = self.archive_identity.clone();
+
+        let decryption_key = if !identity.is_empty()
+        {
+            Some(DecryptionKey::Identity(identity.clone()))
+        }
+        else if !password.is_empty()
+        {
+            Some(DecryptionKey::Password(password.clone()))
+        }
+        else
+        {
+            None
+        };
+
+        // Without the packing settings, an archive opened with a password keeps it.
+        let encryption = self.get_encryption()
+            .or_else(|| match password.is_empty()
             {
-                Ok(extractor) => extractor.to_string(),
-                Err(err) => err.to_string(),
-            };
+                true => None,
+                false => Some(Encryption
+                {
+                    key_source: KeySource::Password(password.clone()),
+                    encrypt_header: self.encrypt_header,
+                }),
+            });
 
-            let paths: Vec<String> = content
-                .lines()
-                .map(|line| line.to_string())
-                .collect();
+        self.status_display.set_content(String::from("Modyfikowanie archiwum..."));
 
-            let mut result_lock = result.lock().unwrap();
-            *result_lock = Some(paths)
+        let result = Arc::clone(&self.archive_content.result);
+        let encrypted = Arc::clone(&self.archive_encrypted.result);
+
+        self.spawn_task(move ||
+        {
+            match update_archive(input_path.clone(), paths_to_add, paths_to_delete, decryption_key, encryption)
+            {
+                Ok(_) =>
+                {
+                    Self::load_archive_content(input_path, &password, &identity, &result, &encrypted);
+                    "Zmodyfikowano archiwum.".to_string()
+                }
+                Err(err_msg) => err_msg,
+            }
         });
 
-        self.processing = false;
+        self.selected_archive_items.clear();
     }
 
     fn do_extraction(&mut self)
@@ -356,20 +849,12 @@ impl Gui // extraction
         let input_path = sanitize_path(&self.input_archive_path);
         let output_directory = sanitize_path(&self.output_directory);
 
-        // Get chosen_paths from clicked position of the selection menu
-        // and remove everything after the actual path,
-        // e.g., "Some", "None" and all that shit.
-        let chosen_paths: Vec<String> = self.selected_archive_items.clone()
-            .into_iter()
-            .map(|path|
-            {
-                let index_to_split = path.rfind(' ').unwrap_or(path.len());
-                path[..index_to_split].to_string()
-            })
-            .collect();
+        let chosen_paths = self.get_chosen_paths();
+        let single_file = is_single_file(&input_path);
 
 
-        if chosen_paths.is_empty()
+        // There is nothing to choose in a single compressed file.
+        if chosen_paths.is_empty() && !single_file
         {
             self.status_display
                 .set_content(String::from("Wybierz pliki do wypakowania."));
@@ -390,18 +875,51 @@ impl Gui // extraction
             return;
         }
 
+        if single_file
+        {
+            self.status_display.set_content(String::from("Dekompresowanie..."));
+            self.spawn_task(move ||
+            {
+                match decompress_file(&input_path, &output_directory)
+                {
+                    Ok(output_path) => format!("Zdekompresowano do {}.", output_path),
+                    Err(err) => err.to_string(),
+                }
+            });
+
+            return;
+        }
+
+        let path_remapping = match self.get_path_remapping()
+        {
+            Ok(path_remapping) => path_remapping,
+            Err(err_msg) =>
+            {
+                self.status_display.set_content(err_msg);
+                return;
+            }
+        };
+
         self.processing = true;
         self.status_display.set_content(String::from("Wypakowywanie..."));
 
-        self.spawn_task(||
+        let trusted_archive = self.trusted_archive;
+        let overwrite_policy = self.overwrite_policy;
+        let password = self.archive_password.clone();
+        let identity = self.archive_identity.clone();
+
+        self.spawn_task(move ||
         {
-            match Extractor::new(input_path)
+            match Self::open_extractor(input_path, &password, &identity)
             {
                 Ok(mut extractor) =>
                 {
+                    extractor.set_trusted(trusted_archive);
+                    extractor.set_overwrite_policy(overwrite_policy);
+                    extractor.set_path_remapping(path_remapping);
                     match extractor.extract_paths(chosen_paths, output_directory)
                     {
-                        Ok(_) => "Wypakowano".to_string(),
+                        Ok(report) => Self::describe_extraction_report(&report),
                         Err(err) => err.to_string(),
                     }
                 },
@@ -412,6 +930,52 @@ impl Gui // extraction
         self.selected_archive_items.clear();
     }
 
+    fn get_path_remapping(&self) -> Result<PathRemapping, String>
+    {
+        let strip_components = match self.strip_components.trim()
+        {
+            "" => 0,
+            strip_components => strip_components.parse::<usize>()
+                .map_err(|_| String::from("Nieprawidłowa liczba katalogów do pominięcia."))?,
+        };
+
+        let prefix_rewrites = parse_path_pairs(&self.prefix_rewrites)
+            .map_err(|line| format!("Nieprawidłowa zamiana ścieżki: {}", line))?;
+
+        Ok(PathRemapping
+        {
+            strip_components,
+            prefix_rewrites,
+            flatten: self.flatten_paths,
+        })
+    }
+
+    fn describe_extraction_report(report: &ExtractionReport) -> String
+    {
+        let mut lines = vec!["Wypakowano.".to_string()];
+
+        if !report.skipped_paths.is_empty()
+        {
+            lines.push(String::from("Pominięte istniejące pliki:"));
+            lines.extend(report.skipped_paths.iter().cloned());
+        }
+
+        if !report.unlinked_paths.is_empty()
+        {
+            lines.push(String::from("Pominięte dowiązania do niewypakowanych plików:"));
+            lines.extend(report.unlinked_paths.iter().cloned());
+        }
+
+        if !report.renamed_paths.is_empty()
+        {
+            lines.push(String::from("Zmienione nazwy:"));
+            lines.extend(report.renamed_paths.iter()
+                .map(|(old_path, new_path)| format!("{} -> {}", old_path, new_path)));
+        }
+
+        lines.join("\n")
+    }
+
     fn extraction_vertical(&mut self, ui: &mut Ui) -> InnerResponse<()>
     {
         ui.vertical(|ui|
@@ -432,10 +996,31 @@ impl Gui // extraction
 
                 if ui.button("Pokaż").clicked()
                 {
+                    self.forget_archive_keys();
                     self.show_archive_content();
                 }
             });
 
+            if *self.archive_encrypted.get_content()
+            {
+                ui.horizontal(|ui|
+                {
+                    ui.add(egui::TextEdit::singleline(&mut self.archive_password)
+                        .password(true)
+                        .hint_text("Hasło archiwum..."));
+
+                    if ui.button("Odblokuj").clicked()
+                    {
+                        self.show_archive_content();
+                    }
+
+                    if ui.button("Wybierz klucz prywatny").clicked()
+                    {
+                        self.select_identity_file();
+                    }
+                });
+            }
+
             ui.vertical(|ui|
             {
                 ui.label("Zawartość archiwum:");
@@ -443,6 +1028,9 @@ impl Gui // extraction
                     .min_scrolled_height(300.0)
                     .max_height(600.0)
                     .show(ui, |ui| self.show_packed_files_selection(ui));
+
+                ui.add(egui::TextEdit::singleline(&mut self.selection_patterns)
+                    .hint_text("Zaznacz wzorcem (np. *.json docs/*)..."));
             });
 
             ui.horizontal(|ui|
@@ -460,10 +1048,73 @@ impl Gui // extraction
                     self.do_extraction();
                 }
             });
+
+            ui.horizontal(|ui|
+            {
+                if ui.button("Dodaj ścieżki do spakowania do archiwum").clicked()
+                {
+                    self.do_modification(self.paths_to_pack.clone(), vec![]);
+                }
+
+                if ui.button("Usuń zaznaczone z archiwum").clicked()
+                {
+                    match self.get_selected_archive_paths()
+                    {
+                        Ok(selected_paths) => self.do_modification(vec![], selected_paths),
+                        Err(err_msg) => self.status_display.set_content(err_msg),
+                    }
+                }
+            });
+
+            ui.checkbox(&mut self.trusted_archive, "Zaufane archiwum (nie sprawdzaj ścieżek)");
+
+            ui.horizontal(|ui|
+            {
+                ui.add(egui::TextEdit::singleline(&mut self.strip_components)
+                    .desired_width(120.0)
+                    .hint_text("Pomiń początkowe katalogi..."));
+
+                ui.checkbox(&mut self.flatten_paths, "Wypakuj bez struktury katalogów");
+
+                ui.add(egui::TextEdit::multiline(&mut self.prefix_rewrites)
+                    .desired_rows(2)
+                    .hint_text("Zamiany ścieżek, np. build/out=bin, po jednej w linii..."));
+            });
+
+            ui.vertical(|ui|
+            {
+                ui.label("Jeśli plik już istnieje:");
+                ui.horizontal(|ui|
+                {
+                    ui.radio_value(&mut self.overwrite_policy, OverwritePolicy::Skip, "Pomiń");
+                    ui.radio_value(&mut self.overwrite_policy, OverwritePolicy::Overwrite, "Nadpisz");
+                    ui.radio_value(&mut self.overwrite_policy, OverwritePolicy::OverwriteIfNewer,
+                                   "Nadpisz starszy");
+                    ui.radio_value(&mut self.overwrite_policy, OverwritePolicy::Rename, "Zmień nazwę");
+                    ui.radio_value(&mut self.overwrite_policy, OverwritePolicy::Fail, "Przerwij");
+                });
+            });
         })
     }
 }
 
+impl Gui // settings
+{
+    /// The budget is given in megabytes. An empty one restores the default.
+    fn apply_memory_budget(&mut self)
+    {
+        match self.memory_budget.trim()
+        {
+            "" => set_memory_budget(None),
+            memory_budget => match memory_budget.parse::<usize>()
+            {
+                Ok(megabytes) if megabytes > 0 => set_memory_budget(Some(megabytes.saturating_mul(1 << 20))),
+                _ => self.status_display.set_content(String::from("Nieprawidłowy limit pamięci.")),
+            },
+        }
+    }
+}
+
 impl eframe::App for Gui
 {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame)
@@ -475,6 +1126,7 @@ impl eframe::App for Gui
                 let archive_content = self.archive_content.get_content();
                 self.display_path_map = get_display_paths(archive_content);
             }
+            self.archive_encrypted.set_new_content();
             self.status_display.set_new_content();
 
             ui.horizontal(|ui|
@@ -483,6 +1135,28 @@ impl eframe::App for Gui
                 self.extraction_vertical(ui);
             });
 
+            ui.horizontal(|ui|
+            {
+                let memory_budget_edit = ui.add(egui::TextEdit::singleline(&mut self.memory_budget)
+                    .desired_width(180.0)
+                    .hint_text("Pamięć na bufory (MB)..."));
+
+                if memory_budget_edit.changed()
+                {
+                    self.apply_memory_budget();
+                }
+
+                // An empty directory restores the default one.
+                let tmp_directory_edit = ui.add(egui::TextEdit::singleline(&mut self.tmp_directory)
+                    .hint_text("Folder na pliki tymczasowe..."));
+
+                if tmp_directory_edit.changed()
+                {
+                    let tmp_directory = sanitize_path(&self.tmp_directory);
+                    set_tmp_directory(Some(tmp_directory).filter(|tmp_directory| !tmp_directory.is_empty()));
+                }
+            });
+
             ui.horizontal(|ui|
             {
                 ui.monospace(self.status_display.get_content());
@@ -34,7 +34,7 @@ impl BitVector
 
     pub fn push_bit(&mut self, bit: Bit)
     {
-        if self.bits_count % 8 == 0
+        if self.bits_count.is_multiple_of(8)
         {
             self.data.push(0);
         }
@@ -4,31 +4,23 @@ use std::io::Write;
 use crate::io_utils::bit_vector::{Bit, BitVector};
 use crate::io_utils::get_memory_buffers_size;
 
-pub struct BitVectorWriter
+pub struct BitVectorWriter<W: Write = File>
 {
-    file_handle: File,
+    file_handle: W,
     buffer: BitVector,
     buffer_bit_count: usize,
 }
 
-impl BitVectorWriter
+impl<W: Write> BitVectorWriter<W>
 {
-    pub fn new(filename: &str) -> Option<BitVectorWriter>
+    pub fn new(file_handle: W) -> BitVectorWriter<W>
     {
-        let file_handle = match File::create(filename)
-        {
-            Ok(file) => file,
-            Err(_) => return None,
-        };
-
-        let file_writer = BitVectorWriter
+        BitVectorWriter
         {
             file_handle,
             buffer: BitVector::new(),
             buffer_bit_count: 8 * get_memory_buffers_size(),
-        };
-
-        Some(file_writer)
+        }
     }
 
     fn flush(&mut self)
@@ -58,7 +50,7 @@ impl BitVectorWriter
     }
 }
 
-impl Drop for BitVectorWriter
+impl<W: Write> Drop for BitVectorWriter<W>
 {
     fn drop(&mut self)
     {
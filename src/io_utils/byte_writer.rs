@@ -2,17 +2,17 @@ use std::fs::File;
 use std::io::Write;
 use crate::io_utils::get_memory_buffers_size;
 
-pub struct ByteWriter
+pub struct ByteWriter<W: Write = File>
 {
-    output_file: File,
+    output_file: W,
     buffer: Vec<u8>,
     buffer_size: usize,
     bytes_in_buffer: usize,
 }
 
-impl ByteWriter
+impl<W: Write> ByteWriter<W>
 {
-    pub fn new(output_file: File) -> Result<ByteWriter, String>
+    pub fn new(output_file: W) -> Result<ByteWriter<W>, String>
     {
         let buffer_size = get_memory_buffers_size();
 
@@ -49,7 +49,7 @@ impl ByteWriter
     }
 }
 
-impl Drop for ByteWriter
+impl<W: Write> Drop for ByteWriter<W>
 {
     fn drop(&mut self)
     {
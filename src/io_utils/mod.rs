@@ -1,6 +1,11 @@
 use rand::Rng;
-use std::env;
-use std::fs::File;
+use std::{env, fs, io, panic, process};
+use std::fs::{File, Metadata, OpenOptions};
+use std::io::ErrorKind;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 use sysinfo::System;
 
 pub mod byte_writer;
@@ -9,9 +14,51 @@ pub mod byte_buffer;
 pub mod bit_vector;
 pub mod bit_vector_writer;
 pub mod path_utils;
+pub mod path_filter;
 
 pub const HUFFMAN_SIGNATURE: u64 = 0xAEFE48;
 pub const LZ77_SIGNATURE: u64 = 0xAEFE77;
+pub const ENCRYPTED_SIGNATURE: u64 = 0xAEFEEC;
+pub const VOLUME_SIGNATURE: u64 = 0xAEFE56;
+pub const SINGLE_FILE_SIGNATURE: u64 = 0xAEFE5A;
+
+/// The memory all the buffers of one task may take together, unless set otherwise.
+pub const DEFAULT_MEMORY_BUDGET: usize = 256 << 20;
+
+/// The environment variable with the memory budget in megabytes.
+pub const MEMORY_BUDGET_VARIABLE: &str = "DIVCOMP_MEMORY_MB";
+
+/// The budget is divided among this many buffers, about as many as one task uses at once.
+const BUFFERS_COUNT: usize = 8;
+
+const MIN_BUFFER_SIZE: usize = 1 << 12;
+
+/// The budget set with set_memory_budget, 0 if not set.
+static MEMORY_BUDGET: AtomicUsize = AtomicUsize::new(0);
+
+/// The environment variable with the directory for the temporary files.
+pub const TMP_DIRECTORY_VARIABLE: &str = "DIVCOMP_TMPDIR";
+
+/// Every temporary file name starts with it, so the ones left behind can be told apart.
+const TMP_FILE_PREFIX: &str = "divcomp-";
+
+/// Every temporary file name ends with it, after the extension, so the temporary files never match
+/// the files of the user, e.g. an archive named divcomp-*.xca.
+const TMP_FILE_SUFFIX: &str = ".divcomp-tmp";
+
+/// The older versions named the temporary files with this many random characters and one of the extensions,
+/// in /tmp, or in %TEMP% outside Unix. The ones they left behind are removed too.
+const LEGACY_TMP_FILENAME_SIZE: usize = 10;
+const LEGACY_TMP_FILE_EXTENSIONS: [&str; 2] = [".unarch", ".resbuf"];
+
+/// A temporary file this old is left by a killed process, not used by a running one.
+const STALE_TMP_FILE_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The directory set with set_tmp_directory, if any.
+static TMP_DIRECTORY: Mutex<Option<String>> = Mutex::new(None);
+
+/// The temporary files which exist now. They are removed if the program is stopped.
+static TMP_FILES: Mutex<Vec<String>> = Mutex::new(Vec::new());
 
 pub struct FileInfo
 {
@@ -19,36 +66,122 @@ pub struct FileInfo
     pub path: String,
 }
 
+/// The signature as written at the beginning of a stream, without the leading zero bytes.
+pub fn get_signature_bytes(signature: u64) -> Vec<u8>
+{
+    signature.to_be_bytes()
+        .into_iter().skip_while(|&byte| byte == 0)
+        .collect()
+}
+
 pub fn bytes_to_u64(bytes: Vec<u8>) -> u64
 {
     let buffer: [u8; 8] = bytes.try_into().unwrap();
     u64::from_be_bytes(buffer)
 }
 
+/// Sets the memory budget in bytes for the tasks started from now on.
+/// None restores the budget from the environment variable or the default one.
+pub fn set_memory_budget(budget: Option<usize>)
+{
+    MEMORY_BUDGET.store(budget.unwrap_or(0), Ordering::Relaxed);
+}
+
+pub fn get_memory_budget() -> usize
+{
+    match MEMORY_BUDGET.load(Ordering::Relaxed)
+    {
+        0 => get_default_memory_budget(),
+        budget => budget,
+    }
+}
+
+/// The budget from the environment variable, or else the default one,
+/// but no more than 1/16 of the RAM. The system is checked once.
+fn get_default_memory_budget() -> usize
+{
+    static DEFAULT_BUDGET: OnceLock<usize> = OnceLock::new();
+
+    *DEFAULT_BUDGET.get_or_init(||
+    {
+        let budget_from_variable = env::var(MEMORY_BUDGET_VARIABLE).ok()
+            .and_then(|megabytes| megabytes.trim().parse::<usize>().ok())
+            .filter(|&megabytes| megabytes > 0)
+            .map(|megabytes| megabytes.saturating_mul(1 << 20));
+
+        if let Some(budget) = budget_from_variable
+        {
+            return budget;
+        }
+
+        let mut system_info = System::new();
+        system_info.refresh_memory();
+
+        match system_info.total_memory() as usize / 16
+        {
+            0 => DEFAULT_MEMORY_BUDGET,
+            memory_share => memory_share.min(DEFAULT_MEMORY_BUDGET),
+        }
+    })
+}
+
+/// The size of one buffer, its share of the memory budget.
 pub fn get_memory_buffers_size() -> usize
 {
-    let mut system_info = System::new_all();
-    system_info.refresh_all();
+    (get_memory_budget() / BUFFERS_COUNT).max(MIN_BUFFER_SIZE)
+}
 
-    let total_memory = system_info.total_memory() as usize;
-    total_memory / 16
+/// Sets the directory for the temporary files created from now on.
+/// None restores the directory from the environment variable or the system one.
+pub fn set_tmp_directory(tmp_directory: Option<String>)
+{
+    *TMP_DIRECTORY.lock().unwrap() = tmp_directory;
 }
 
-pub fn create_tmp_file(extension: &str) -> Option<FileInfo>
+pub fn get_tmp_directory() -> String
 {
-    let tmp_directory = if cfg!(unix)
+    if let Some(tmp_directory) = TMP_DIRECTORY.lock().unwrap().clone()
     {
-        "/tmp".to_string()
+        return tmp_directory;
     }
-    else
+
+    env::var(TMP_DIRECTORY_VARIABLE).ok()
+        .filter(|tmp_directory| !tmp_directory.is_empty())
+        .unwrap_or_else(|| env::temp_dir().to_string_lossy().to_string())
+}
+
+pub fn create_tmp_file(extension: &str) -> Option<FileInfo>
+{
+    create_tmp_file_in(&get_tmp_directory(), &format!("{extension}{TMP_FILE_SUFFIX}"), true)
+}
+
+/// Creates a temporary file in the directory of the given path,
+/// so it can replace that path by renaming. Its permissions are those of a new file.
+pub fn create_sibling_tmp_file(path: &str, extension: &str) -> Option<FileInfo>
+{
+    create_tmp_file_in(get_parent_directory(path)?, &format!("{extension}{TMP_FILE_SUFFIX}"), false)
+}
+
+fn get_parent_directory(path: &str) -> Option<&str>
+{
+    let directory = Path::new(path).parent()?
+        .to_str()?;
+
+    match directory.is_empty()
     {
-        env::var("TEMP").unwrap_or_else(|_| String::from("."))
-    };
+        true => Some("."),
+        false => Some(directory),
+    }
+}
 
+/// The file is created anew, so an existing file or a planted symlink is never used.
+/// A private file may be accessed only by the owner. It is opened for reading and writing.
+fn create_tmp_file_in(tmp_directory: &str, extension: &str, private: bool) -> Option<FileInfo>
+{
     const FILENAME_SIZE: usize = 10;
     const MAX_ATTEMPTS_COUNT: usize = 10;
 
-    let mut rng = rand::thread_rng();
+    let mut rng = rand::rng();
 
     for _ in 0..MAX_ATTEMPTS_COUNT
     {
@@ -56,19 +189,220 @@ pub fn create_tmp_file(extension: &str) -> Option<FileInfo>
             .map(|_| rng.sample(rand::distr::Alphanumeric))
             .map(char::from)
             .collect();
-        let path = format!("{tmp_directory}/{filename}{extension}");
+        let path = format!("{tmp_directory}/{TMP_FILE_PREFIX}{filename}{extension}");
 
-        if let Ok(file) = File::create(&path)
+        let mut open_options = OpenOptions::new();
+        open_options.read(true)
+            .write(true)
+            .create_new(true);
+
+        #[cfg(unix)]
+        if private
         {
-            let file_info = FileInfo
+            std::os::unix::fs::OpenOptionsExt::mode(&mut open_options, 0o600);
+        }
+
+        match open_options.open(&path)
+        {
+            Ok(file) =>
             {
-                handle: file,
-                path,
-            };
+                TMP_FILES.lock().unwrap().push(path.clone());
+
+                let file_info = FileInfo
+                {
+                    handle: file,
+                    path,
+                };
 
-            return Some(file_info);
+                return Some(file_info);
+            }
+
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+            Err(_) => return None,
         }
     }
 
     None
 }
+
+/// Removes the temporary file. A file moved away already is only forgotten.
+pub fn remove_tmp_file(path: &str) -> io::Result<()>
+{
+    forget_tmp_file(path);
+
+    match fs::remove_file(path)
+    {
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Renames the finished temporary file to the path. The data reaches the disk first,
+/// so after a crash the path holds the whole file or none.
+pub fn persist_tmp_file(tmp_file_path: &str, path: &str) -> io::Result<()>
+{
+    OpenOptions::new()
+        .write(true)
+        .open(tmp_file_path)?
+        .sync_all()?;
+
+    fs::rename(tmp_file_path, path)?;
+    forget_tmp_file(tmp_file_path);
+
+    // The rename is saved with the directory.
+    #[cfg(unix)]
+    if let Some(directory) = get_parent_directory(path)
+    {
+        File::open(directory)?
+            .sync_all()?;
+    }
+
+    Ok(())
+}
+
+/// The temporary file is kept, e.g. it has been renamed to the archive.
+pub fn forget_tmp_file(path: &str)
+{
+    TMP_FILES.lock().unwrap()
+        .retain(|tmp_file_path| tmp_file_path != path);
+}
+
+/// Removes all the temporary files of the process, which is about to stop.
+pub fn remove_all_tmp_files()
+{
+    // A panic which stops the program may have poisoned the lock.
+    let mut tmp_files = TMP_FILES.lock()
+        .unwrap_or_else(|err| err.into_inner());
+
+    for path in tmp_files.drain(..)
+    {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// Removes the temporary files when the program is interrupted or terminated by a signal,
+/// or when a panic aborts it. Otherwise the files are removed by their owners.
+pub fn remove_tmp_files_on_exit()
+{
+    let _ = ctrlc::set_handler(||
+    {
+        remove_all_tmp_files();
+        process::exit(130);
+    });
+
+    // An unwinding panic stops only one task, whose files are removed as it unwinds.
+    if cfg!(panic = "abort")
+    {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |panic_info|
+        {
+            remove_all_tmp_files();
+            default_hook(panic_info);
+        }));
+    }
+}
+
+/// Removes the temporary files left in the temporary directory by the runs
+/// which could not clean up, e.g. were killed or lost power.
+pub fn remove_stale_tmp_files()
+{
+    remove_stale_files_in(&get_tmp_directory(), |filename, metadata|
+        is_tmp_filename(filename) && is_private_file(metadata));
+
+    if let Some(legacy_tmp_directory) = get_legacy_tmp_directory()
+    {
+        remove_stale_files_in(&legacy_tmp_directory, |filename, metadata|
+            is_legacy_tmp_filename(filename) && is_own_file(metadata));
+    }
+}
+
+/// Removes the temporary files left next to the given path, e.g. by an archive never finished there.
+/// They have the permissions of a new file, so only their owner is checked.
+pub fn remove_stale_sibling_tmp_files(path: &str)
+{
+    if let Some(directory) = get_parent_directory(path)
+    {
+        remove_stale_files_in(directory, |filename, metadata| is_tmp_filename(filename) && is_own_file(metadata));
+    }
+}
+
+fn remove_stale_files_in(directory: &str, is_tmp_file: impl Fn(&str, &Metadata) -> bool)
+{
+    let entries = match fs::read_dir(directory)
+    {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten()
+    {
+        let filename = entry.file_name().to_string_lossy().to_string();
+
+        // The metadata of a symlink is not followed.
+        let metadata = match entry.metadata()
+        {
+            Ok(metadata) if metadata.is_file() && is_tmp_file(&filename, &metadata) => metadata,
+            _ => continue,
+        };
+
+        let stale = metadata.modified().ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|age| age > STALE_TMP_FILE_AGE);
+
+        if stale
+        {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+fn is_tmp_filename(filename: &str) -> bool
+{
+    filename.starts_with(TMP_FILE_PREFIX) && filename.ends_with(TMP_FILE_SUFFIX)
+}
+
+fn is_legacy_tmp_filename(filename: &str) -> bool
+{
+    LEGACY_TMP_FILE_EXTENSIONS.iter()
+        .filter_map(|extension| filename.strip_suffix(extension))
+        .any(|name| name.len() == LEGACY_TMP_FILENAME_SIZE && name.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+/// The older versions fell back to the current directory without %TEMP%, which is not swept.
+fn get_legacy_tmp_directory() -> Option<String>
+{
+    match cfg!(unix)
+    {
+        true => Some("/tmp".to_string()),
+        false => env::var("TEMP").ok(),
+    }
+}
+
+/// Only the private files of the current user may have been created by this program.
+#[cfg(unix)]
+fn is_private_file(metadata: &Metadata) -> bool
+{
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.mode() & 0o777 == 0o600 && is_own_file(metadata)
+}
+
+#[cfg(not(unix))]
+fn is_private_file(_metadata: &Metadata) -> bool
+{
+    true
+}
+
+#[cfg(unix)]
+fn is_own_file(metadata: &Metadata) -> bool
+{
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.uid() == nix::unistd::geteuid().as_raw()
+}
+
+#[cfg(not(unix))]
+fn is_own_file(_metadata: &Metadata) -> bool
+{
+    true
+}
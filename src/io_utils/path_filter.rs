@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+
+use globset::{GlobBuilder, GlobMatcher, GlobSet, GlobSetBuilder};
+
+use crate::io_utils::path_utils::{get_superpath, is_path_within};
+
+
+/// Selects archive paths with glob patterns. A directory matching a pattern takes its content with it.
+/// A pattern ending with "/" matches only directories.
+/// A pattern starting with "/" is matched from the archive root, any other at any depth.
+/// "*" does not match "/", "**" does.
+#[derive(Clone)]
+pub struct PathFilter
+{
+    include: GlobSet,
+    include_directories: GlobSet,
+    exclude: GlobSet,
+    exclude_directories: GlobSet,
+    has_include_patterns: bool,
+}
+
+impl Default for PathFilter
+{
+    fn default() -> Self
+    {
+        PathFilter::new(&[], &[]).unwrap()
+    }
+}
+
+/// Returns the glob sets matching any path and only directories.
+fn build_glob_sets(patterns: &[String]) -> Result<(GlobSet, GlobSet), String>
+{
+    let mut any_path = GlobSetBuilder::new();
+    let mut directories = GlobSetBuilder::new();
+
+    for pattern in patterns.iter().map(|pattern| pattern.trim()).filter(|pattern| !pattern.is_empty())
+    {
+        let (pattern, directories_only) = match pattern.strip_suffix('/')
+        {
+            Some(pattern) => (pattern, true),
+            None => (pattern, false),
+        };
+
+        let pattern = match pattern.strip_prefix('/')
+        {
+            Some(anchored_pattern) => anchored_pattern.to_string(),
+            None if pattern.starts_with("**") => pattern.to_string(),
+            None => format!("**/{}", pattern),
+        };
+
+        let glob = GlobBuilder::new(&pattern)
+            .literal_separator(true)
+            .build()
+            .map_err(|_| format!("Invalid pattern {}.", pattern))?;
+
+        match directories_only
+        {
+            true => directories.add(glob),
+            false => any_path.add(glob),
+        };
+    }
+
+    let any_path = any_path.build()
+        .map_err(|_| "Invalid patterns.".to_string())?;
+    let directories = directories.build()
+        .map_err(|_| "Invalid patterns.".to_string())?;
+
+    Ok((any_path, directories))
+}
+
+/// Returns the path and all the directories above it, from the top one.
+fn get_path_and_ancestors(path: &str) -> impl Iterator<Item = &str>
+{
+    path.match_indices('/')
+        .map(|(index, _)| &path[..index])
+        .chain(std::iter::once(path))
+}
+
+impl PathFilter
+{
+    pub fn new(include_patterns: &[String], exclude_patterns: &[String]) -> Result<PathFilter, String>
+    {
+        let (include, include_directories) = build_glob_sets(include_patterns)?;
+        let (exclude, exclude_directories) = build_glob_sets(exclude_patterns)?;
+
+        let path_filter = PathFilter
+        {
+            has_include_patterns: !include.is_empty() || !include_directories.is_empty(),
+            include,
+            include_directories,
+            exclude,
+            exclude_directories,
+        };
+
+        Ok(path_filter)
+    }
+
+    /// A path matches if it or any directory above it matches.
+    fn matches(path: &str, is_directory: bool, any_path: &GlobSet, directories: &GlobSet) -> bool
+    {
+        get_path_and_ancestors(path).any(|ancestor|
+        {
+            let ancestor_is_directory = is_directory || ancestor.len() < path.len();
+            any_path.is_match(ancestor) || (ancestor_is_directory && directories.is_match(ancestor))
+        })
+    }
+
+    pub fn is_excluded(&self, path: &str, is_directory: bool) -> bool
+    {
+        // "dir/**" also matches the directory itself.
+        let excluded_content = is_directory && self.exclude.is_match(format!("{}/", path));
+
+        excluded_content || Self::matches(path, is_directory, &self.exclude, &self.exclude_directories)
+    }
+
+    /// Without include patterns everything is included.
+    pub fn is_included(&self, path: &str, is_directory: bool) -> bool
+    {
+        !self.has_include_patterns
+            || Self::matches(path, is_directory, &self.include, &self.include_directories)
+    }
+
+    pub fn has_include_patterns(&self) -> bool
+    {
+        self.has_include_patterns
+    }
+}
+
+enum Selection
+{
+    Path(String),
+    Pattern
+    {
+        glob: GlobMatcher,
+        // A pattern without "/" matches the names at any depth.
+        any_depth: bool,
+        // The leading directories without wildcards.
+        base: String,
+    },
+}
+
+/// Selects archive paths to extract. A selection is either an archive path
+/// or a glob pattern, e.g. "docs/*.md" or "*.json". A selected directory takes its content with it.
+/// Paths are compared by whole components, so "docs" does not select "docs_old".
+pub struct PathSelection
+{
+    selections: Vec<Selection>,
+    // Not selected, but holding selected paths, with their bases.
+    directories_on_the_way: HashMap<String, String>,
+}
+
+fn is_pattern(selection: &str) -> bool
+{
+    selection.contains(['*', '?', '[', '{'])
+}
+
+impl PathSelection
+{
+    pub fn new(selections: &[String]) -> Result<PathSelection, String>
+    {
+        let mut parsed_selections = vec![];
+
+        for selection in selections.iter().map(|selection| selection.trim().trim_end_matches('/'))
+        {
+            if !is_pattern(selection)
+            {
+                parsed_selections.push(Selection::Path(selection.to_string()));
+                continue;
+            }
+
+            let glob = GlobBuilder::new(selection)
+                .literal_separator(true)
+                .build()
+                .map_err(|_| selection.to_string())?
+                .compile_matcher();
+
+            let base = selection.split('/')
+                .take_while(|component| !is_pattern(component))
+                .collect::<Vec<&str>>()
+                .join("/");
+
+            parsed_selections.push(Selection::Pattern
+            {
+                glob,
+                any_depth: !selection.contains('/'),
+                base,
+            });
+        }
+
+        Ok(PathSelection
+        {
+            selections: parsed_selections,
+            directories_on_the_way: HashMap::new(),
+        })
+    }
+
+    /// A path matching a pattern may lie deeper than the pattern base,
+    /// so the directories between them are selected too.
+    pub fn add_directories_on_the_way<'a>(&mut self, archive_paths: impl Iterator<Item = &'a str>)
+    {
+        let mut directories_on_the_way = HashMap::new();
+
+        for path in archive_paths
+        {
+            let base = match self.get_base(path)
+            {
+                Some(base) => base,
+                None => continue,
+            };
+
+            for ancestor in get_path_and_ancestors(path)
+                .filter(|ancestor| ancestor.len() > base.len() && ancestor.len() < path.len())
+            {
+                if self.get_base(ancestor).is_none()
+                {
+                    directories_on_the_way.insert(ancestor.to_string(), base.clone());
+                }
+            }
+        }
+
+        self.directories_on_the_way.extend(directories_on_the_way);
+    }
+
+    /// Returns the leading part of a selected path which is left out on extraction,
+    /// or None if the path is not selected.
+    /// For a selected archive path it is the directory containing it,
+    /// for a pattern the directories before the first wildcard.
+    pub fn get_base(&self, path: &str) -> Option<String>
+    {
+        self.selections.iter().find_map(|selection| match selection
+        {
+            Selection::Path(selected_path) => is_path_within(selected_path, path)
+                .then(|| get_superpath(selected_path)),
+
+            Selection::Pattern { glob, any_depth, base } => get_path_and_ancestors(path)
+                .any(|ancestor| match any_depth
+                {
+                    true => glob.is_match(ancestor.rsplit('/').next().unwrap_or(ancestor)),
+                    false => glob.is_match(ancestor),
+                })
+                .then(|| base.clone()),
+        })
+            .or_else(|| self.directories_on_the_way.get(path).cloned())
+    }
+
+    pub fn is_selected(&self, path: &str) -> bool
+    {
+        self.get_base(path).is_some()
+    }
+}
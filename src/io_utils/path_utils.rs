@@ -1,13 +1,25 @@
 use std::collections::HashMap;
-use std::path::Path;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Component, Path};
 
 pub const ARCHIVE_EXTENSION: &str = "xca";
+pub const SINGLE_FILE_EXTENSION: &str = "xcz";
 
 pub fn is_a_subdirectory(superpath: &str, subpath: &str) -> bool
 {
     subpath.to_string().starts_with(superpath)
 }
 
+/// True if the path is the root itself or lies inside it, comparing whole path components.
+pub fn is_path_within(root: &str, path: &str) -> bool
+{
+    match path.strip_prefix(root)
+    {
+        Some(rest) => rest.is_empty() || rest.starts_with('/') || root.ends_with('/'),
+        None => false,
+    }
+}
+
 pub fn get_superpath(path: &str) -> String
 {
     Path::new(path)
@@ -18,6 +30,57 @@ pub fn get_superpath(path: &str) -> String
         .to_string()
 }
 
+/// Resolves the "." and ".." components of a relative path.
+/// Returns None if the path is absolute or goes above its beginning.
+pub fn normalize_relative_path(path: &str) -> Option<String>
+{
+    let mut components: Vec<&str> = vec![];
+    for component in Path::new(path).components()
+    {
+        match component
+        {
+            Component::Normal(name) => components.push(name.to_str()?),
+            Component::CurDir => {},
+            Component::ParentDir =>
+            {
+                components.pop()?;
+            },
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    Some(components.join("/"))
+}
+
+/// Reads a list of paths separated with the separator, e.g. "\n" for a manifest file
+/// or "\0" for the output of "find -print0". Empty entries are skipped.
+pub fn read_path_list(reader: impl Read, separator: u8) -> Result<Vec<String>, String>
+{
+    let mut paths = vec![];
+
+    for entry in BufReader::new(reader).split(separator)
+    {
+        let entry = entry
+            .map_err(|_| "Could not read the list of paths.".to_string())?;
+        let path = String::from_utf8(entry)
+            .map_err(|_| "The list of paths is not valid UTF-8.".to_string())?;
+
+        // A manifest may come with Windows line endings.
+        let path = match separator
+        {
+            b'\n' => path.trim_end_matches('\r').to_string(),
+            _ => path,
+        };
+
+        if !path.is_empty()
+        {
+            paths.push(path);
+        }
+    }
+
+    Ok(paths)
+}
+
 pub fn sanitize_path(path: &String) -> String
 {
     Path::new(path)
@@ -33,6 +96,7 @@ pub fn sanitize_output_path(path: &String) -> String
 {
     let path_initially_sanitized = sanitize_path(path);
     match path_initially_sanitized.ends_with(ARCHIVE_EXTENSION)
+        || path_initially_sanitized.ends_with(SINGLE_FILE_EXTENSION)
     {
         true => path_initially_sanitized,
         false => format!("{}.{}", path_initially_sanitized, ARCHIVE_EXTENSION)
@@ -1,20 +1,21 @@
 use std::fs::File;
-use std::io::Read;
+use std::io;
+use std::io::{ErrorKind, Read};
 use crate::io_utils::bit_vector::Bit;
 use crate::io_utils::get_memory_buffers_size;
 
-pub struct UniversalReader
+pub struct UniversalReader<R: Read = File>
 {
-    file_handle: File,
+    file_handle: R,
     buffer: Vec<u8>,
     bytes_in_buffer: usize,
     bytes_read_from_buffer: usize,
-    bits_read_total: usize,
+    bits_read_from_byte: usize,
 }
 
-impl UniversalReader
+impl<R: Read> UniversalReader<R>
 {
-    pub fn new(file_handle: File) -> UniversalReader
+    pub fn new(file_handle: R) -> UniversalReader<R>
     {
         UniversalReader
         {
@@ -22,63 +23,78 @@ impl UniversalReader
             buffer: vec![0; get_memory_buffers_size()],
             bytes_in_buffer: 0,
             bytes_read_from_buffer: 0,
-            bits_read_total: 0,
+            bits_read_from_byte: 0,
         }
     }
 
-    fn refill_buffer(&mut self)
+    fn refill_buffer(&mut self) -> io::Result<()>
     {
-        self.bytes_in_buffer = self.file_handle.read(&mut self.buffer)
-            .unwrap();
+        // Pipes and sockets may be interrupted before reading anything.
+        self.bytes_in_buffer = loop
+        {
+            match self.file_handle.read(&mut self.buffer)
+            {
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                result => break result?,
+            }
+        };
         self.bytes_read_from_buffer = 0;
+
+        Ok(())
     }
 
-    pub fn read_byte(&mut self) -> Option<u8>
+    /// None at the end of the input.
+    pub fn read_byte(&mut self) -> io::Result<Option<u8>>
     {
         if self.bytes_read_from_buffer == self.bytes_in_buffer
         {
-            self.refill_buffer();
+            self.refill_buffer()?;
             if self.bytes_in_buffer == 0
             {
-                return None;
+                return Ok(None);
             }
         }
 
         let data = self.buffer[self.bytes_read_from_buffer];
         self.bytes_read_from_buffer += 1;
-        self.bits_read_total += 8;
 
-        Some(data)
+        Ok(Some(data))
     }
 
-    pub fn read_bit(&mut self) -> Option<Bit>
+    /// The bits are read from the most significant one. Reading bytes must not start in the middle of a byte.
+    pub fn read_bit(&mut self) -> io::Result<Option<Bit>>
     {
-        if self.bytes_in_buffer == 0 || (self.bits_read_total) % (8 * self.bytes_in_buffer) == 0
+        if self.bytes_read_from_buffer == self.bytes_in_buffer
         {
-            self.refill_buffer();
+            self.refill_buffer()?;
             if self.bytes_in_buffer == 0
             {
-                return None;
+                return Ok(None);
             }
         }
 
-        let bit_index_in_buffer = self.bits_read_total % (8 * self.bytes_in_buffer);
+        let byte = self.buffer[self.bytes_read_from_buffer];
+        let bit = (byte >> (7 - self.bits_read_from_byte)) & 1;
 
-        let byte_index_in_buffer = bit_index_in_buffer / 8;
-        let bit_index_in_byte = bit_index_in_buffer % 8;
+        // Every read may fill the buffer with a different number of bytes, so count them one by one.
+        self.bits_read_from_byte += 1;
+        if self.bits_read_from_byte == 8
+        {
+            self.bits_read_from_byte = 0;
+            self.bytes_read_from_buffer += 1;
+        }
 
-        let bit = (self.buffer[byte_index_in_buffer] >> (7 - bit_index_in_byte)) & 1;
-        self.bits_read_total += 1;
-        Some(bit)
+        Ok(Some(bit))
     }
 }
 
-impl Iterator for UniversalReader
+impl<R: Read> Iterator for UniversalReader<R>
 {
-    type Item = u8;
+    type Item = io::Result<u8>;
 
     fn next(&mut self) -> Option<Self::Item>
     {
         self.read_byte()
+            .transpose()
     }
 }
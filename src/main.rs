@@ -3,14 +3,30 @@
 mod io_utils;
 mod archive;
 mod compress;
+mod encrypt;
 mod gui;
+mod cli;
 
 fn main()
 {
-    let archive_path = std::env::args().nth(1);
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+
+    io_utils::remove_tmp_files_on_exit();
+    io_utils::remove_stale_tmp_files();
+
+    // Commands run without the window.
+    if arguments.first().is_some_and(|argument| cli::COMMANDS.contains(&argument.as_str()))
+    {
+        std::process::exit(cli::run(&arguments));
+    }
+
+    let archive_path = arguments.first().cloned();
 
     if let Err(err) = gui::run("Archiwizator boży", archive_path)
     {
         eprintln!("{}", err);
     }
+
+    // The window was closed while a task was running.
+    io_utils::remove_all_tmp_files();
 }